@@ -0,0 +1,39 @@
+// build.rs
+// Links the firmware with the linker script of the selected platform, or at the OpenSBI
+// payload address with the `sbi` feature
+// The per-hart stack size (BENCH_STACK_SIZE, see params.rs) is passed to the linker script as
+// _hart_stack_size
+
+use std::path::Path;
+
+/// Same default as params::DEFAULT_STACK_SIZE
+const DEFAULT_STACK_SIZE: usize = 64 * 1024;
+
+fn main() {
+    let script = if std::env::var_os("CARGO_FEATURE_SBI").is_some() {
+        "sbi.lds"
+    } else if std::env::var_os("CARGO_FEATURE_SIFIVE_U").is_some()
+        && std::env::var_os("CARGO_FEATURE_VIRT").is_none()
+    {
+        "sifive_u.lds"
+    } else {
+        "virt.lds"
+    };
+    let path = Path::new(&std::env::var("CARGO_MANIFEST_DIR").unwrap())
+        .join("src")
+        .join("lds")
+        .join(script);
+    println!("cargo:rerun-if-changed={}", path.display());
+    println!("cargo:rustc-link-arg-bins=-T{}", path.display());
+
+    // params.rs validates the value, a malformed one fails the build there
+    println!("cargo:rerun-if-env-changed=BENCH_STACK_SIZE");
+    let stack_size = std::env::var("BENCH_STACK_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(DEFAULT_STACK_SIZE);
+    println!(
+        "cargo:rustc-link-arg-bins=--defsym=_hart_stack_size={}",
+        stack_size
+    );
+}
@@ -0,0 +1,283 @@
+// compare.rs
+// Comparison of a report against a baseline
+// A baseline is any aggregate report written by the runner (e.g. a copy of
+// target/runner/report.json from a known good commit); runs are matched by
+// benchmark/mode/size/harts and compared on one of the timing statistics
+
+use crate::report::{Key, Record, RunResult};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Timing statistic used for the comparison
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Statistic {
+    Min,
+    Avg,
+    Max,
+}
+
+impl Statistic {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "min" => Some(Statistic::Min),
+            "avg" => Some(Statistic::Avg),
+            "max" => Some(Statistic::Max),
+            _ => None,
+        }
+    }
+
+    fn value(&self, record: &Record) -> u64 {
+        match self {
+            Statistic::Min => record.min_ns,
+            Statistic::Avg => record.avg_ns,
+            Statistic::Max => record.max_ns,
+        }
+    }
+}
+
+pub struct Comparison {
+    pub key: Key,
+    pub baseline_ns: u64,
+    pub current_ns: u64,
+}
+
+impl Comparison {
+    /// Relative change of the time, positive when slower
+    pub fn change(&self) -> f64 {
+        if self.baseline_ns == 0 {
+            return 0.0;
+        }
+        (self.current_ns as f64 - self.baseline_ns as f64) / self.baseline_ns as f64
+    }
+
+    /// `threshold` is a relative change, e.g. 0.05 for 5%
+    pub fn is_regression(&self, threshold: f64) -> bool {
+        self.change() > threshold
+    }
+}
+
+/// The timed runs, by configuration
+/// Failed runs are left out, they are reported by their status
+fn timings(results: &[RunResult], statistic: Statistic) -> BTreeMap<&Key, u64> {
+    results
+        .iter()
+        .filter(|result| result.status.is_success())
+        .filter_map(|result| {
+            let record = result.record.as_ref()?;
+            Some((&result.key, statistic.value(record)))
+        })
+        .collect()
+}
+
+pub struct Summary {
+    pub comparisons: Vec<Comparison>,
+    /// Configurations of the baseline without a successful run in the current report
+    pub missing: Vec<Key>,
+}
+
+pub fn compare(current: &[RunResult], baseline: &[RunResult], statistic: Statistic) -> Summary {
+    let current = timings(current, statistic);
+    let mut summary = Summary {
+        comparisons: Vec::new(),
+        missing: Vec::new(),
+    };
+    for (key, baseline_ns) in timings(baseline, statistic) {
+        match current.get(key) {
+            Some(&current_ns) => summary.comparisons.push(Comparison {
+                key: key.clone(),
+                baseline_ns,
+                current_ns,
+            }),
+            None => summary.missing.push(key.clone()),
+        }
+    }
+    summary
+}
+
+impl Summary {
+    pub fn regressions(&self, threshold: f64) -> usize {
+        self.comparisons
+            .iter()
+            .filter(|comparison| comparison.is_regression(threshold))
+            .count()
+    }
+
+    pub fn print(&self, threshold: f64) {
+        println!(
+            "{:<44} {:>14} {:>14} {:>9}",
+            "configuration", "baseline", "current", "change"
+        );
+        for comparison in &self.comparisons {
+            let flag = if comparison.is_regression(threshold) {
+                "  REGRESSION"
+            } else {
+                ""
+            };
+            println!(
+                "{:<44} {:>14} {:>14} {:>+8.1}%{}",
+                comparison.key.to_string(),
+                format!("{:?}", Duration::from_nanos(comparison.baseline_ns)),
+                format!("{:?}", Duration::from_nanos(comparison.current_ns)),
+                comparison.change() * 100.0,
+                flag
+            );
+        }
+        for key in &self.missing {
+            println!("{:<44} missing from the current report", key.to_string());
+        }
+        println!(
+            "{} regressions beyond {:.1}% in {} compared runs",
+            self.regressions(threshold),
+            threshold * 100.0,
+            self.comparisons.len()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::Status;
+
+    fn key(benchmark: &str, harts: u64) -> Key {
+        Key {
+            benchmark: benchmark.to_string(),
+            mode: "parallel".to_string(),
+            size: 16,
+            harts,
+        }
+    }
+
+    fn result(key: Key, status: Status, avg_ns: u64) -> RunResult {
+        RunResult {
+            record: Some(Record {
+                benchmark: key.benchmark.clone(),
+                element_type: "i32".to_string(),
+                size: key.size,
+                harts: key.harts,
+                mode: key.mode.clone(),
+                iterations: 1,
+                min_ns: avg_ns - 10,
+                avg_ns,
+                max_ns: avg_ns + 10,
+                verification: "passed".to_string(),
+            }),
+            key,
+            status,
+        }
+    }
+
+    fn comparison(baseline_ns: u64, current_ns: u64) -> Comparison {
+        Comparison {
+            key: key("convolution", 1),
+            baseline_ns,
+            current_ns,
+        }
+    }
+
+    #[test]
+    fn improvement() {
+        let comparison = comparison(1000, 800);
+        assert_eq!(comparison.change(), -0.2);
+        assert!(!comparison.is_regression(0.05));
+    }
+
+    #[test]
+    fn regression_threshold() {
+        // at the threshold is not a regression yet
+        assert_eq!(comparison(1000, 1050).change(), 0.05);
+        assert!(!comparison(1000, 1050).is_regression(0.05));
+        assert!(comparison(1000, 1051).is_regression(0.05));
+        assert!(comparison(1000, 2000).is_regression(0.05));
+    }
+
+    #[test]
+    fn zero_baseline() {
+        assert_eq!(comparison(0, 1000).change(), 0.0);
+        assert!(!comparison(0, 1000).is_regression(0.05));
+    }
+
+    #[test]
+    fn statistics() {
+        let record = result(key("convolution", 1), Status::Passed, 100)
+            .record
+            .unwrap();
+        assert_eq!(Statistic::parse("min").unwrap().value(&record), 90);
+        assert_eq!(Statistic::parse("avg").unwrap().value(&record), 100);
+        assert_eq!(Statistic::parse("max").unwrap().value(&record), 110);
+        assert_eq!(Statistic::parse("median"), None);
+    }
+
+    #[test]
+    fn runs_are_matched_by_key() {
+        let baseline = [
+            result(key("convolution", 1), Status::Passed, 1000),
+            result(key("convolution", 4), Status::Passed, 400),
+        ];
+        // in a different order, and with a run the baseline does not have
+        let current = [
+            result(key("matrix_multiplication", 4), Status::Passed, 100),
+            result(key("convolution", 4), Status::Passed, 500),
+            result(key("convolution", 1), Status::Passed, 900),
+        ];
+        let summary = compare(&current, &baseline, Statistic::Avg);
+        let compared: Vec<_> = summary
+            .comparisons
+            .iter()
+            .map(|comparison| {
+                (
+                    comparison.key.clone(),
+                    comparison.baseline_ns,
+                    comparison.current_ns,
+                )
+            })
+            .collect();
+        assert_eq!(
+            compared,
+            [
+                (key("convolution", 1), 1000, 900),
+                (key("convolution", 4), 400, 500)
+            ]
+        );
+        assert!(summary.missing.is_empty());
+        assert_eq!(summary.regressions(0.05), 1);
+        assert_eq!(summary.regressions(0.5), 0);
+    }
+
+    #[test]
+    fn missing_from_the_current_report() {
+        let baseline = [
+            result(key("convolution", 1), Status::Passed, 1000),
+            result(key("convolution", 4), Status::Passed, 400),
+        ];
+        let current = [result(key("convolution", 1), Status::Passed, 1000)];
+        let summary = compare(&current, &baseline, Statistic::Avg);
+        assert_eq!(summary.comparisons.len(), 1);
+        assert_eq!(summary.missing, [key("convolution", 4)]);
+        assert_eq!(summary.regressions(0.05), 0);
+    }
+
+    #[test]
+    fn failed_runs() {
+        // a failed current run counts as missing, a failed baseline run is not compared
+        let baseline = [
+            result(key("convolution", 1), Status::Passed, 1000),
+            result(key("convolution", 4), Status::Panicked, 400),
+        ];
+        let current = [
+            result(key("convolution", 1), Status::VerificationFailed, 1000),
+            result(key("convolution", 4), Status::Passed, 4000),
+        ];
+        let summary = compare(&current, &baseline, Statistic::Avg);
+        assert!(summary.comparisons.is_empty());
+        assert_eq!(summary.missing, [key("convolution", 1)]);
+
+        let no_record = RunResult {
+            key: key("convolution", 1),
+            status: Status::Timeout,
+            record: None,
+        };
+        let summary = compare(&[no_record], &baseline, Statistic::Avg);
+        assert_eq!(summary.missing, [key("convolution", 1)]);
+    }
+}
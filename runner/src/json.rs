@@ -0,0 +1,412 @@
+// json.rs
+// Minimal JSON values, enough for the firmware reports and the aggregate report files
+
+use std::fmt::{self, Display, Write};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    /// Keys keep their order, so the files written are stable
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        self.as_f64()
+            .filter(|n| *n >= 0.0 && n.fract() == 0.0)
+            .map(|n| n as u64)
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<u64> for Value {
+    fn from(n: u64) -> Self {
+        Value::Number(n as f64)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+/// Compact form, on a single line
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) if n.is_finite() => write!(f, "{}", n),
+            Value::Number(_) => f.write_str("null"),
+            Value::String(s) => write_string(f, s),
+            Value::Array(values) => {
+                f.write_char('[')?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_char(']')
+            }
+            Value::Object(members) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: &'static str,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at byte {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub fn parse(text: &str) -> Result<Value, ParseError> {
+    let mut parser = Parser {
+        bytes: text.as_bytes(),
+        position: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.position != parser.bytes.len() {
+        return Err(parser.error("trailing characters"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &'static str) -> ParseError {
+        ParseError {
+            position: self.position,
+            message,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), ParseError> {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(self.error("unexpected character"))
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Value) -> Result<Value, ParseError> {
+        if self.bytes[self.position..].starts_with(keyword.as_bytes()) {
+            self.position += keyword.len();
+            Ok(value)
+        } else {
+            Err(self.error("invalid literal"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b't') => self.keyword("true", Value::Bool(true)),
+            Some(b'f') => self.keyword("false", Value::Bool(false)),
+            Some(b'n') => self.keyword("null", Value::Null),
+            Some(c) if c == b'-' || c.is_ascii_digit() => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, ParseError> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.expect(b':')?;
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(Value::Object(members));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, ParseError> {
+        self.expect(b'[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(Value::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(Value::Array(values));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        if self.peek() != Some(b'"') {
+            return Err(self.error("expected a string"));
+        }
+        self.position += 1;
+        let mut s = String::new();
+        loop {
+            let start = self.position;
+            while self.peek().is_some_and(|c| c != b'"' && c != b'\\') {
+                self.position += 1;
+            }
+            // the input is a &str and the loop stops on ASCII, so this is a char boundary
+            s.push_str(std::str::from_utf8(&self.bytes[start..self.position]).unwrap());
+            match self.peek() {
+                Some(b'"') => {
+                    self.position += 1;
+                    return Ok(s);
+                }
+                Some(b'\\') => {
+                    self.position += 1;
+                    let escaped = self
+                        .peek()
+                        .ok_or_else(|| self.error("unexpected end of input"))?;
+                    self.position += 1;
+                    match escaped {
+                        b'"' => s.push('"'),
+                        b'\\' => s.push('\\'),
+                        b'/' => s.push('/'),
+                        b'b' => s.push('\u{8}'),
+                        b'f' => s.push('\u{c}'),
+                        b'n' => s.push('\n'),
+                        b'r' => s.push('\r'),
+                        b't' => s.push('\t'),
+                        b'u' => {
+                            let hex = self
+                                .bytes
+                                .get(self.position..self.position + 4)
+                                .and_then(|hex| std::str::from_utf8(hex).ok())
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .ok_or_else(|| self.error("invalid unicode escape"))?;
+                            self.position += 4;
+                            // surrogate pairs are not needed for our files
+                            s.push(char::from_u32(hex).unwrap_or('\u{fffd}'));
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                }
+                _ => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, ParseError> {
+        let start = self.position;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_digit() || matches!(c, b'-' | b'+' | b'.' | b'e' | b'E'))
+        {
+            self.position += 1;
+        }
+        std::str::from_utf8(&self.bytes[start..self.position])
+            .ok()
+            .and_then(|n| n.parse().ok())
+            .map(Value::Number)
+            .ok_or_else(|| self.error("invalid number"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> &'static str {
+        parse(text).unwrap_err().message
+    }
+
+    #[test]
+    fn round_trip() {
+        let value = Value::Object(vec![
+            ("null".to_string(), Value::Null),
+            ("bool".to_string(), Value::Bool(true)),
+            ("integer".to_string(), 42u64.into()),
+            ("negative".to_string(), Value::Number(-1.5e-3)),
+            (
+                "string".to_string(),
+                "quote \" backslash \\ newline \n bell \u{7} é".into(),
+            ),
+            (
+                "array".to_string(),
+                Value::Array(vec![Value::Array(vec![]), Value::Object(vec![]), "".into()]),
+            ),
+        ]);
+        assert_eq!(parse(&value.to_string()).unwrap(), value);
+    }
+
+    #[test]
+    fn non_finite_numbers_are_written_as_null() {
+        assert_eq!(Value::Number(f64::NAN).to_string(), "null");
+        assert_eq!(
+            parse(&Value::Number(f64::INFINITY).to_string()).unwrap(),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn whitespace_and_escapes() {
+        let value =
+            parse(" { \"a\" : [ 1 , -2.5e1 ] ,\n\t\"b\":\"\\/\\b\\f\\r\\t\\u00e9\" } ").unwrap();
+        assert_eq!(
+            value.get("a").and_then(Value::as_array),
+            Some(&[Value::Number(1.0), Value::Number(-25.0)][..])
+        );
+        assert_eq!(
+            value.get("b").and_then(Value::as_str),
+            Some("/\u{8}\u{c}\r\t\u{e9}")
+        );
+    }
+
+    #[test]
+    fn accessors() {
+        let value = parse("{\"n\":3,\"f\":1.5,\"m\":-1,\"s\":\"x\"}").unwrap();
+        assert_eq!(value.get("n").and_then(Value::as_u64), Some(3));
+        assert_eq!(value.get("f").and_then(Value::as_u64), None);
+        assert_eq!(value.get("m").and_then(Value::as_u64), None);
+        assert_eq!(value.get("s").and_then(Value::as_u64), None);
+        assert_eq!(value.get("missing"), None);
+        assert_eq!(Value::Null.get("n"), None);
+    }
+
+    #[test]
+    fn unterminated_string() {
+        assert_eq!(error("\"abc"), "unterminated string");
+        assert_eq!(error("\"abc\\"), "unexpected end of input");
+    }
+
+    #[test]
+    fn bad_escapes() {
+        assert_eq!(error("\"\\x\""), "invalid escape");
+        assert_eq!(error("\"\\u12\""), "invalid unicode escape");
+        assert_eq!(error("\"\\uzzzz\""), "invalid unicode escape");
+    }
+
+    #[test]
+    fn trailing_characters() {
+        let error = parse("{} x").unwrap_err();
+        assert_eq!(error.message, "trailing characters");
+        assert_eq!(error.position, 3);
+        assert_eq!(self::error("1 2"), "trailing characters");
+    }
+
+    #[test]
+    fn malformed_values() {
+        assert_eq!(error(""), "unexpected end of input");
+        assert_eq!(error("tru"), "invalid literal");
+        assert_eq!(error("1-2"), "invalid number");
+        assert_eq!(error("-"), "invalid number");
+        assert_eq!(error("[1 2]"), "expected ',' or ']'");
+        assert_eq!(error("{\"a\":1 \"b\":2}"), "expected ',' or '}'");
+        assert_eq!(error("{\"a\" 1}"), "unexpected character");
+        assert_eq!(error("{1:2}"), "expected a string");
+        assert_eq!(error("[1,]"), "unexpected character");
+    }
+}
@@ -0,0 +1,363 @@
+// Host-side runner of the benchmark suite
+// Builds the firmware for every benchmark/mode/size combination, runs each image under QEMU
+// for every hart count, collects the records printed with the `report_json` feature and
+// writes an aggregate report, optionally comparing it against a baseline report
+// Run it from the repository with `cargo runner [options]`, see the alias in .cargo/config.toml
+
+mod compare;
+mod json;
+mod qemu;
+mod report;
+
+use compare::Statistic;
+use qemu::{Exit, Qemu};
+use report::{Key, Record, RunResult, Status};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode};
+use std::time::Duration;
+
+const BOARD_TARGET: &str = "riscv64gc-unknown-none-elf";
+const FIRMWARE_PACKAGE: &str = "gpu4s_bench_riscv";
+
+const USAGE: &str = "\
+Usage: cargo runner [options]
+       cargo runner compare <report> <baseline> [--threshold <percent>] [--statistic <name>]
+
+Options:
+  --benchmarks <list>  benchmarks to run [default: matrix_multiplication,convolution]
+  --modes <list>       scheduling modes to run [default: sequential,parallel,dynamic]
+                       sweep runs its own sizes on a single hart
+  --sizes <list>       matrix sides [default: 4,8,16]
+  --harts <list>       hart counts for the parallel modes [default: 1,2,4]
+  --iterations <n>     timed repetitions in each run [default: 5]
+  --timeout <secs>     time limit of a single QEMU run [default: 60]
+  --release            build the firmware in release mode
+  --output <path>      aggregate report [default: target/runner/report.json]
+  --baseline <path>    compare the report against a previous one
+  --threshold <percent>
+                       slowdown flagged as a regression [default: 5]
+  --statistic <name>   timing compared, min, avg or max [default: avg]
+  --help               print this message
+
+Lists are comma separated. The exit status is 1 if a run failed and 2 if a run
+regressed against the baseline.";
+
+const EXIT_FAILED_RUN: u8 = 1;
+const EXIT_REGRESSION: u8 = 2;
+
+/// Modes that only use hart 0
+const SINGLE_HART_MODES: [&str; 2] = ["sequential", "sweep"];
+
+struct Options {
+    benchmarks: Vec<String>,
+    modes: Vec<String>,
+    sizes: Vec<u64>,
+    harts: Vec<u64>,
+    iterations: u64,
+    timeout: Duration,
+    release: bool,
+    output: Option<PathBuf>,
+    baseline: Option<PathBuf>,
+    /// Relative change, e.g. 0.05 for 5%
+    threshold: f64,
+    statistic: Statistic,
+    /// Positional arguments
+    files: Vec<PathBuf>,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut options = Options {
+            benchmarks: list("matrix_multiplication,convolution"),
+            modes: list("sequential,parallel,dynamic"),
+            sizes: numbers("4,8,16")?,
+            harts: numbers("1,2,4")?,
+            iterations: 5,
+            timeout: Duration::from_secs(60),
+            release: false,
+            output: None,
+            baseline: None,
+            threshold: 0.05,
+            statistic: Statistic::Avg,
+            files: Vec::new(),
+        };
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+            match arg.as_str() {
+                "--benchmarks" => options.benchmarks = list(&value()?),
+                "--modes" => options.modes = list(&value()?),
+                "--sizes" => options.sizes = numbers(&value()?)?,
+                "--harts" => options.harts = numbers(&value()?)?,
+                "--iterations" => {
+                    let iterations = value()?;
+                    options.iterations = iterations
+                        .parse()
+                        .ok()
+                        .filter(|iterations| *iterations > 0)
+                        .ok_or_else(|| format!("invalid iterations {}", iterations))?;
+                }
+                "--timeout" => {
+                    let secs = value()?;
+                    let secs = secs
+                        .parse()
+                        .map_err(|_| format!("invalid timeout {}", secs))?;
+                    options.timeout = Duration::from_secs(secs);
+                }
+                "--release" => options.release = true,
+                "--output" => options.output = Some(PathBuf::from(value()?)),
+                "--baseline" => options.baseline = Some(PathBuf::from(value()?)),
+                "--threshold" => {
+                    let percent = value()?;
+                    let percent: f64 = percent
+                        .parse()
+                        .ok()
+                        .filter(|percent: &f64| *percent >= 0.0)
+                        .ok_or_else(|| format!("invalid threshold {}", percent))?;
+                    options.threshold = percent / 100.0;
+                }
+                "--statistic" => {
+                    let statistic = value()?;
+                    options.statistic = Statistic::parse(&statistic)
+                        .ok_or_else(|| format!("invalid statistic {}", statistic))?;
+                }
+                "--help" => return Ok(None),
+                _ if !arg.starts_with('-') => options.files.push(PathBuf::from(arg)),
+                _ => return Err(format!("unknown option {}", arg)),
+            }
+        }
+        Ok(Some(options))
+    }
+}
+
+fn list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn numbers(value: &str) -> Result<Vec<u64>, String> {
+    list(value)
+        .iter()
+        .map(|item| item.parse().map_err(|_| format!("invalid number {}", item)))
+        .collect()
+}
+
+/// The repository, which holds the firmware package and the cargo configuration
+fn workspace_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .expect("the runner is a member of the workspace")
+        .to_path_buf()
+}
+
+/// Builds the firmware image and returns its path
+fn build(
+    root: &Path,
+    benchmark: &str,
+    mode: &str,
+    size: u64,
+    iterations: u64,
+    release: bool,
+) -> Option<PathBuf> {
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let features = format!("{},{},report_json,exit_when_done", benchmark, mode);
+    let mut command = Command::new(cargo);
+    // the cargo configuration of the repository selects the board target
+    command
+        .current_dir(root)
+        .args([
+            "build",
+            "--package",
+            FIRMWARE_PACKAGE,
+            "--features",
+            &features,
+        ])
+        .env("BENCH_SIDE", size.to_string())
+        .env("BENCH_ITERATIONS", iterations.to_string());
+    if release {
+        command.arg("--release");
+    }
+    match command.status() {
+        Ok(status) if status.success() => {}
+        Ok(_) => return None,
+        Err(error) => {
+            eprintln!("cannot run cargo: {}", error);
+            return None;
+        }
+    }
+    let target_dir = std::env::var_os("CARGO_TARGET_DIR")
+        .map_or_else(|| root.join("target"), |dir| root.join(dir));
+    let profile = if release { "release" } else { "debug" };
+    Some(
+        target_dir
+            .join(BOARD_TARGET)
+            .join(profile)
+            .join(FIRMWARE_PACKAGE),
+    )
+}
+
+/// Runs an image and turns its output into results
+/// Without records a single failed result is returned for `key`
+fn run(qemu: &Qemu, kernel: &Path, key: Key, timeout: Duration) -> Vec<RunResult> {
+    let status = match qemu.run(kernel, key.harts as usize, timeout) {
+        Ok(output) => {
+            let status = match output.exit {
+                Exit::Code(code) => Status::from_exit_code(code),
+                Exit::Killed => Status::Exited(-1),
+                Exit::Timeout => Status::Timeout,
+            };
+            let records = Record::parse_output(&output.stdout);
+            if !records.is_empty() {
+                return records
+                    .into_iter()
+                    .map(|record| RunResult::from_record(record, status))
+                    .collect();
+            }
+            if status.is_success() {
+                Status::NoReport
+            } else {
+                status
+            }
+        }
+        Err(error) => {
+            eprintln!("cannot run QEMU: {}", error);
+            Status::LaunchFailed
+        }
+    };
+    vec![RunResult {
+        key,
+        status,
+        record: None,
+    }]
+}
+
+/// Compares `results` against the baseline, if one was given
+/// Returns false if a run regressed
+fn check_baseline(results: &[RunResult], options: &Options) -> Result<bool, String> {
+    let Some(path) = &options.baseline else {
+        return Ok(true);
+    };
+    let baseline = report::read(path).map_err(|error| error.to_string())?;
+    let summary = compare::compare(results, &baseline, options.statistic);
+    summary.print(options.threshold);
+    Ok(summary.regressions(options.threshold) == 0)
+}
+
+/// `compare` subcommand, compares two existing reports
+fn compare_reports(mut options: Options) -> Result<ExitCode, String> {
+    let [report, baseline] = <[PathBuf; 2]>::try_from(std::mem::take(&mut options.files))
+        .map_err(|_| "compare needs a report and a baseline".to_string())?;
+    let results = report::read(&report).map_err(|error| error.to_string())?;
+    options.baseline = Some(baseline);
+    Ok(if check_baseline(&results, &options)? {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(EXIT_REGRESSION)
+    })
+}
+
+fn run_suite(options: Options) -> Result<ExitCode, String> {
+    if !options.files.is_empty() {
+        return Err(format!(
+            "unexpected argument {}",
+            options.files[0].display()
+        ));
+    }
+    let root = workspace_root();
+    let qemu = Qemu::from_cargo_config(&root).map_err(|error| error.to_string())?;
+
+    let mut results = Vec::new();
+    for benchmark in &options.benchmarks {
+        for mode in &options.modes {
+            let single_hart = SINGLE_HART_MODES.contains(&mode.as_str());
+            // sweep chooses its sizes at runtime
+            let sizes = if mode == "sweep" {
+                &[0][..]
+            } else {
+                &options.sizes[..]
+            };
+            let harts = if single_hart {
+                &[1][..]
+            } else {
+                &options.harts[..]
+            };
+            for &size in sizes {
+                eprintln!("Building {} {} size {}", benchmark, mode, size);
+                let kernel = build(
+                    &root,
+                    benchmark,
+                    mode,
+                    size,
+                    options.iterations,
+                    options.release,
+                );
+                for &harts in harts {
+                    let key = Key {
+                        benchmark: benchmark.clone(),
+                        mode: mode.clone(),
+                        size,
+                        harts,
+                    };
+                    let Some(kernel) = &kernel else {
+                        results.push(RunResult {
+                            key,
+                            status: Status::BuildFailed,
+                            record: None,
+                        });
+                        continue;
+                    };
+                    eprintln!("Running {}", key);
+                    results.extend(run(&qemu, kernel, key, options.timeout));
+                }
+            }
+        }
+    }
+
+    report::print_table(&results);
+    let output = options
+        .output
+        .clone()
+        .unwrap_or_else(|| root.join("target").join("runner").join("report.json"));
+    report::write(&output, &results)
+        .map_err(|error| format!("cannot write {}: {}", output.display(), error))?;
+    println!("Report written to {}", output.display());
+
+    let no_regressions = check_baseline(&results, &options)?;
+    Ok(
+        if !results.iter().all(|result| result.status.is_success()) {
+            ExitCode::from(EXIT_FAILED_RUN)
+        } else if !no_regressions {
+            ExitCode::from(EXIT_REGRESSION)
+        } else {
+            ExitCode::SUCCESS
+        },
+    )
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1).peekable();
+    let compare = args.next_if(|arg| arg == "compare").is_some();
+    let options = match Options::parse(args) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+    let result = if compare {
+        compare_reports(options)
+    } else {
+        run_suite(options)
+    };
+    result.unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        ExitCode::FAILURE
+    })
+}
@@ -0,0 +1,110 @@
+// qemu.rs
+// Launches the firmware under QEMU with the command line of the cargo runner
+
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Target section of .cargo/config.toml holding the runner
+const BOARD_TARGET: &str = "[target.riscv64gc-unknown-none-elf]";
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub struct Qemu {
+    program: String,
+    args: Vec<String>,
+}
+
+pub enum Exit {
+    Code(i32),
+    /// Killed by a signal
+    Killed,
+    Timeout,
+}
+
+pub struct Output {
+    pub exit: Exit,
+    /// Serial console output
+    pub stdout: String,
+}
+
+impl Qemu {
+    /// Uses the runner of the board target in `<root>/.cargo/config.toml`, so the runs
+    /// see the same machine as `cargo run`
+    pub fn from_cargo_config(root: &Path) -> io::Result<Self> {
+        let path = root.join(".cargo").join("config.toml");
+        let config = std::fs::read_to_string(&path)?;
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: no runner for {}", path.display(), BOARD_TARGET),
+            )
+        };
+        let runner = config
+            .lines()
+            .map(str::trim)
+            .skip_while(|line| *line != BOARD_TARGET)
+            .skip(1)
+            .take_while(|line| !line.starts_with('['))
+            .find_map(|line| {
+                let (key, value) = line.split_once('=')?;
+                (key.trim() == "runner")
+                    .then(|| value.trim().trim_matches(|c| c == '"' || c == '\''))
+            })
+            .ok_or_else(invalid)?;
+        let mut words = runner.split_whitespace().map(str::to_string);
+        let program = words.next().ok_or_else(invalid)?;
+        Ok(Qemu {
+            program,
+            args: words.collect(),
+        })
+    }
+
+    /// The runner line with the number of harts replaced, followed by the kernel path
+    fn command(&self, kernel: &Path, harts: usize) -> Command {
+        let mut command = Command::new(&self.program);
+        let mut args = self.args.iter();
+        while let Some(arg) = args.next() {
+            command.arg(arg);
+            if arg == "-smp" {
+                args.next();
+                command.arg(harts.to_string());
+            }
+        }
+        command.arg(kernel);
+        command
+    }
+
+    /// Runs `kernel` on `harts` harts, killing QEMU after `timeout`
+    pub fn run(&self, kernel: &Path, harts: usize, timeout: Duration) -> io::Result<Output> {
+        let mut child = self
+            .command(kernel, harts)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let reader = thread::spawn(move || {
+            let mut bytes = Vec::new();
+            let _ = stdout.read_to_end(&mut bytes);
+            // the console may carry anything, e.g. after a crash
+            String::from_utf8_lossy(&bytes).into_owned()
+        });
+
+        let deadline = Instant::now() + timeout;
+        let exit = loop {
+            if let Some(status) = child.try_wait()? {
+                break status.code().map_or(Exit::Killed, Exit::Code);
+            }
+            if Instant::now() >= deadline {
+                child.kill()?;
+                child.wait()?;
+                break Exit::Timeout;
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+        let stdout = reader.join().unwrap_or_default();
+        Ok(Output { exit, stdout })
+    }
+}
@@ -0,0 +1,403 @@
+// report.rs
+// Results of the runs: the records printed by the firmware (`report_json` feature, see
+// src/report.rs of the firmware) and the aggregate report written by the runner
+
+use crate::json::{self, Value};
+use std::fmt::{self, Display};
+use std::path::Path;
+use std::{fs, io};
+
+/// A run as reported by the firmware
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub benchmark: String,
+    pub element_type: String,
+    pub size: u64,
+    pub harts: u64,
+    pub mode: String,
+    pub iterations: u64,
+    pub min_ns: u64,
+    pub avg_ns: u64,
+    pub max_ns: u64,
+    pub verification: String,
+}
+
+impl Record {
+    pub fn from_json(value: &Value) -> Option<Self> {
+        let string = |key| value.get(key).and_then(Value::as_str).map(str::to_string);
+        let number = |key| value.get(key).and_then(Value::as_u64);
+        Some(Record {
+            benchmark: string("benchmark")?,
+            element_type: string("element_type")?,
+            size: number("size")?,
+            harts: number("harts")?,
+            mode: string("mode")?,
+            iterations: number("iterations")?,
+            min_ns: number("min_ns")?,
+            avg_ns: number("avg_ns")?,
+            max_ns: number("max_ns")?,
+            verification: string("verification")?,
+        })
+    }
+
+    /// Extracts the records from the serial output of a run, other lines are ignored
+    pub fn parse_output(output: &str) -> Vec<Self> {
+        output
+            .lines()
+            .map(|line| strip_line_prefix(line.trim()))
+            .filter(|line| line.starts_with('{'))
+            .filter_map(|line| json::parse(line).ok())
+            .filter_map(|value| Record::from_json(&value))
+            .collect()
+    }
+}
+
+/// Removes the `[hart time] ` prefix of the lines printed with the `line_prefix` feature
+fn strip_line_prefix(line: &str) -> &str {
+    line.strip_prefix('[')
+        .and_then(|rest| rest.split_once("] "))
+        .map_or(line, |(_, rest)| rest.trim_start())
+}
+
+/// How a run ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Passed,
+    VerificationFailed,
+    Panicked,
+    WatchdogExpired,
+    /// QEMU exited with an unexpected status
+    Exited(i32),
+    Timeout,
+    BuildFailed,
+    /// QEMU could not be started
+    LaunchFailed,
+    /// The run completed without printing a record
+    NoReport,
+}
+
+impl Status {
+    /// Maps the exit status of QEMU, set by the firmware through the test finisher
+    pub fn from_exit_code(code: i32) -> Self {
+        match code {
+            0 => Status::Passed,
+            1 => Status::Panicked,
+            2 => Status::WatchdogExpired,
+            code => Status::Exited(code),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        *self == Status::Passed
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "passed" => Status::Passed,
+            "verification_failed" => Status::VerificationFailed,
+            "panic" => Status::Panicked,
+            "watchdog" => Status::WatchdogExpired,
+            "timeout" => Status::Timeout,
+            "build_failed" => Status::BuildFailed,
+            "launch_failed" => Status::LaunchFailed,
+            "no_report" => Status::NoReport,
+            s => Status::Exited(s.strip_prefix("exit_")?.parse().ok()?),
+        })
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Status::Passed => f.write_str("passed"),
+            Status::VerificationFailed => f.write_str("verification_failed"),
+            Status::Panicked => f.write_str("panic"),
+            Status::WatchdogExpired => f.write_str("watchdog"),
+            Status::Exited(code) => write!(f, "exit_{}", code),
+            Status::Timeout => f.write_str("timeout"),
+            Status::BuildFailed => f.write_str("build_failed"),
+            Status::LaunchFailed => f.write_str("launch_failed"),
+            Status::NoReport => f.write_str("no_report"),
+        }
+    }
+}
+
+/// Identifies a configuration across reports
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Key {
+    pub benchmark: String,
+    pub mode: String,
+    pub size: u64,
+    pub harts: u64,
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} size {} harts {}",
+            self.benchmark, self.mode, self.size, self.harts
+        )
+    }
+}
+
+/// An entry of the aggregate report
+/// Runs that did not produce a record keep the configuration that was requested
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunResult {
+    pub key: Key,
+    pub status: Status,
+    pub record: Option<Record>,
+}
+
+impl RunResult {
+    pub fn from_record(record: Record, status: Status) -> Self {
+        let status = if status.is_success() && record.verification == "failed" {
+            Status::VerificationFailed
+        } else {
+            status
+        };
+        RunResult {
+            key: Key {
+                benchmark: record.benchmark.clone(),
+                mode: record.mode.clone(),
+                size: record.size,
+                harts: record.harts,
+            },
+            status,
+            record: Some(record),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let mut members = vec![
+            ("benchmark".to_string(), self.key.benchmark.as_str().into()),
+            ("mode".to_string(), self.key.mode.as_str().into()),
+            ("size".to_string(), self.key.size.into()),
+            ("harts".to_string(), self.key.harts.into()),
+            (
+                "status".to_string(),
+                self.status.to_string().as_str().into(),
+            ),
+        ];
+        if let Some(record) = &self.record {
+            members.extend([
+                (
+                    "element_type".to_string(),
+                    record.element_type.as_str().into(),
+                ),
+                ("iterations".to_string(), record.iterations.into()),
+                ("min_ns".to_string(), record.min_ns.into()),
+                ("avg_ns".to_string(), record.avg_ns.into()),
+                ("max_ns".to_string(), record.max_ns.into()),
+                (
+                    "verification".to_string(),
+                    record.verification.as_str().into(),
+                ),
+            ]);
+        }
+        Value::Object(members)
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        let string = |key| value.get(key).and_then(Value::as_str).map(str::to_string);
+        let number = |key| value.get(key).and_then(Value::as_u64);
+        let key = Key {
+            benchmark: string("benchmark")?,
+            mode: string("mode")?,
+            size: number("size")?,
+            harts: number("harts")?,
+        };
+        let status = Status::parse(&string("status")?)?;
+        // the record fields are only there if the run produced one
+        let record = number("avg_ns").and_then(|_| {
+            Some(Record {
+                benchmark: key.benchmark.clone(),
+                element_type: string("element_type")?,
+                size: key.size,
+                harts: key.harts,
+                mode: key.mode.clone(),
+                iterations: number("iterations")?,
+                min_ns: number("min_ns")?,
+                avg_ns: number("avg_ns")?,
+                max_ns: number("max_ns")?,
+                verification: string("verification")?,
+            })
+        });
+        Some(RunResult {
+            key,
+            status,
+            record,
+        })
+    }
+}
+
+/// Writes the aggregate report, one result per line
+pub fn write(path: &Path, results: &[RunResult]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut text = String::from("{\"results\":[\n");
+    for (i, result) in results.iter().enumerate() {
+        let separator = if i + 1 < results.len() { "," } else { "" };
+        text.push_str(&format!("{}{}\n", result.to_json(), separator));
+    }
+    text.push_str("]}\n");
+    fs::write(path, text)
+}
+
+/// Reads an aggregate report written by `write`
+pub fn read(path: &Path) -> io::Result<Vec<RunResult>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let text = fs::read_to_string(path)?;
+    let value =
+        json::parse(&text).map_err(|error| invalid(format!("{}: {}", path.display(), error)))?;
+    value
+        .get("results")
+        .and_then(Value::as_array)
+        .ok_or_else(|| invalid(format!("{}: no results array", path.display())))?
+        .iter()
+        .map(|result| {
+            RunResult::from_json(result)
+                .ok_or_else(|| invalid(format!("{}: invalid result {}", path.display(), result)))
+        })
+        .collect()
+}
+
+/// Prints the results as a table
+pub fn print_table(results: &[RunResult]) {
+    println!(
+        "{:<24} {:<10} {:>6} {:>5} {:>20} {:>14}",
+        "benchmark", "mode", "size", "harts", "status", "avg time"
+    );
+    for result in results {
+        let avg = result.record.as_ref().map_or("-".to_string(), |record| {
+            format!("{:?}", std::time::Duration::from_nanos(record.avg_ns))
+        });
+        println!(
+            "{:<24} {:<10} {:>6} {:>5} {:>20} {:>14}",
+            result.key.benchmark,
+            result.key.mode,
+            result.key.size,
+            result.key.harts,
+            result.status.to_string(),
+            avg
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINE: &str = "{\"benchmark\":\"matrix_multiplication\",\"element_type\":\"i32\",\"size\":16,\"harts\":4,\"mode\":\"parallel\",\"paging\":\"off\",\"placement\":\"ram\",\"iterations\":1,\"min_ns\":1200,\"avg_ns\":1300,\"max_ns\":1400,\"verification\":\"passed\"}";
+
+    fn record() -> Record {
+        Record {
+            benchmark: "matrix_multiplication".to_string(),
+            element_type: "i32".to_string(),
+            size: 16,
+            harts: 4,
+            mode: "parallel".to_string(),
+            iterations: 1,
+            min_ns: 1200,
+            avg_ns: 1300,
+            max_ns: 1400,
+            verification: "passed".to_string(),
+        }
+    }
+
+    #[test]
+    fn record_from_firmware_line() {
+        let value = json::parse(LINE).unwrap();
+        assert_eq!(Record::from_json(&value), Some(record()));
+    }
+
+    #[test]
+    fn record_with_missing_field() {
+        let value = json::parse("{\"benchmark\":\"convolution\",\"size\":16}").unwrap();
+        assert_eq!(Record::from_json(&value), None);
+    }
+
+    #[test]
+    fn records_from_serial_output() {
+        let output = format!(
+            "Platform: virt\r\nResult: [1, 2]\r\n  {}\r\n{{not json\r\n{{\"benchmark\":\"x\"}}\r\n",
+            LINE
+        );
+        assert_eq!(Record::parse_output(&output), vec![record()]);
+    }
+
+    #[test]
+    fn records_from_prefixed_output() {
+        let output = format!(
+            "[0    0.001234] Platform: virt\r\n[0    1.500000] {}\r\n",
+            LINE
+        );
+        assert_eq!(Record::parse_output(&output), vec![record()]);
+    }
+
+    #[test]
+    fn status_from_exit_code() {
+        assert_eq!(Status::from_exit_code(0), Status::Passed);
+        assert_eq!(Status::from_exit_code(1), Status::Panicked);
+        assert_eq!(Status::from_exit_code(2), Status::WatchdogExpired);
+        assert_eq!(Status::from_exit_code(3), Status::Exited(3));
+        assert!(Status::from_exit_code(0).is_success());
+        assert!(!Status::from_exit_code(1).is_success());
+    }
+
+    #[test]
+    fn status_round_trip() {
+        for status in [
+            Status::Passed,
+            Status::VerificationFailed,
+            Status::Panicked,
+            Status::WatchdogExpired,
+            Status::Exited(-1),
+            Status::Timeout,
+            Status::BuildFailed,
+            Status::LaunchFailed,
+            Status::NoReport,
+        ] {
+            assert_eq!(Status::parse(&status.to_string()), Some(status));
+        }
+        assert_eq!(Status::parse("exit_x"), None);
+        assert_eq!(Status::parse("unknown"), None);
+    }
+
+    #[test]
+    fn failed_verification() {
+        let mut failed = record();
+        failed.verification = "failed".to_string();
+        let result = RunResult::from_record(failed, Status::Passed);
+        assert_eq!(result.status, Status::VerificationFailed);
+        assert_eq!(result.key.harts, 4);
+        // a failure of the run itself is kept
+        let mut failed = record();
+        failed.verification = "failed".to_string();
+        let result = RunResult::from_record(failed, Status::WatchdogExpired);
+        assert_eq!(result.status, Status::WatchdogExpired);
+    }
+
+    #[test]
+    fn result_round_trip() {
+        let results = [
+            RunResult::from_record(record(), Status::Passed),
+            RunResult {
+                key: Key {
+                    benchmark: "convolution".to_string(),
+                    mode: "dynamic".to_string(),
+                    size: 32,
+                    harts: 2,
+                },
+                status: Status::BuildFailed,
+                record: None,
+            },
+        ];
+        for result in &results {
+            let value = json::parse(&result.to_json().to_string()).unwrap();
+            assert_eq!(RunResult::from_json(&value).as_ref(), Some(result));
+        }
+    }
+}
@@ -7,16 +7,22 @@
 .section .text.init
 .global _start
 _start:
+	# Monitor harts below FIRST_HART (see platform.rs) cannot run the firmware,
+	# they are parked for good before touching memory or the FPU
+	csrr	t0, mhartid
+	li		t1, {FIRST_HART}
+	bltu	t0, t1, 4f
 	# Any hardware threads (hart) that are not bootstrapping
 	# need to wait for an IPI
-	csrr	t0, mhartid
-	bnez	t0, 3f
+	bne		t0, t1, 3f
 	# SATP should be zero, but let's make sure
 	csrw	satp, zero
 .option push
 .option norelax
 	la		gp, _global_pointer
 .option pop
+	# Keep the device tree pointer passed in a1, the loop below clobbers it
+	mv		s1, a1
 	# The BSS section is expected to be zero
 	la 		a0, _bss_start
 	la		a1, _bss_end
@@ -34,12 +40,16 @@ _start:
 	la		sp, _stack_end
 	# We use mret here so that the mstatus register
 	# is properly updated.
+	# Interrupts stay disabled (MPIE clear) until kinit has set up the trap frame
+	# of the hart in mscratch, then it enables them.
     # THE 1 << 13 SHOULD ENABLE THE FPU, https://blog.stephenmarz.com/2020/06/14/hardware-floating-point/
-    #                MPP     |  MPIE    |    MIE   |      FS   
-	li		t0, (0b11 << 11) | (1 << 7) | (1 << 3) | (0b01 << 13)
+    #                MPP     |      FS   
+	li		t0, (0b11 << 11) | (0b01 << 13)
 	csrw	mstatus, t0
-    csrr	a0, mhartid
-	la		t1, main
+	# kinit gets the logical hart id, which is 0 on the boot hart
+	li		a0, 0
+	mv		a1, s1
+	la		t1, kinit
 	csrw	mepc, t1
 	la		t2, asm_trap_vector
 	csrw	mtvec, t2
@@ -58,30 +68,43 @@ _start:
 	# We only use additional harts to run user-space programs, although this may
 	# change.
 
+	# From here on the hart is known by its logical id, counted from FIRST_HART
+	csrr	a0, mhartid
+	addi	a0, a0, -{FIRST_HART}
+
+	# Harts that do not fit in the stack region are parked for good
+	la		t1, _stack_end
+	la		t2, _stack_start
+	sub		t1, t1, t2
+	li		t0, {STACK_SIZE}
+	divu	t1, t1, t0
+	bgeu	a0, t1, 4f
+
 	# We divide up the stack so the harts aren't clobbering one another.
 	la		sp, _stack_end
-	li		t0, 0x10000
-	csrr	a0, mhartid
+	li		t0, {STACK_SIZE}
 	mul		t0, t0, a0
 	sub		sp, sp, t0
 
-	# The parked harts will be put into machine mode with interrupts enabled.
-	li		t0, 0b11 << 11 | (1 << 7) | (1 << 13)
+	# The parked harts will be put into machine mode with interrupts disabled
+	# (MPIE clear), so that the MSIP wakes up wfi in kinit without trapping.
+	li		t0, 0b11 << 11 | (1 << 13)
 	csrw	mstatus, t0
 	# Allow for MSIP (Software interrupt). We will write the MSIP from hart #0 to
 	# awaken these parked harts.
 	li		t3, (1 << 3)
 	csrw	mie, t3
 	# Machine's exception program counter (MEPC) is set to the Rust initialization
-	# code and waiting loop.
-	la		t1, main
+	# code and waiting loop. a0 holds the logical hart id and a1 still holds the
+	# device tree pointer from the firmware.
+	la		t1, kinit
 	csrw	mepc, t1
 	# Machine's trap vector base address is set to `m_trap_vector`, for
 	# "machine" trap vector. The Rust initialization routines will give each
 	# hart its own trap frame. We can use the same trap function and distinguish
 	# between each hart by looking at the trap frame.
-	# la		t2, m_trap_vector
-	# csrw	mtvec, t2
+	la		t2, asm_trap_vector
+	csrw	mtvec, t2
 	# Whenever our hart is done initializing, we want it to return to the waiting
 	# loop, which is just below mret.
 	la		ra, 4f
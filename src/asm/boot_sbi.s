@@ -0,0 +1,89 @@
+# boot_sbi.s
+# Entry points in supervisor mode, as the payload of OpenSBI (`sbi` feature)
+# OpenSBI enters _start on a single hart, picked at random, with a0 = mhartid
+# and a1 = the device tree, the other harts are stopped. kinit starts them at
+# _start_hart through the HSM extension (see sbi.rs) once the machine is known.
+# The logical hart id (see platform.rs) is kept in tp, as supervisor mode cannot
+# read mhartid.
+.option norvc
+.section .text.init
+.global _start
+_start:
+	# The boot hart must be FIRST_HART, which is logical hart 0: any other hart
+	# starts it in its place with the same device tree and stops, until kinit
+	# starts it again as a worker
+	li		t0, {FIRST_HART}
+	beq		a0, t0, 2f
+	# sbi_hart_start(FIRST_HART, _start, dtb)
+	mv		a2, a1
+	mv		a0, t0
+	la		a1, _start
+	li		a7, {EXT_HSM}
+	li		a6, 0
+	ecall
+	# sbi_hart_stop()
+	li		a7, {EXT_HSM}
+	li		a6, 1
+	ecall
+1:
+	wfi
+	j		1b
+2:
+	# SATP should be zero, but let's make sure
+	csrw	satp, zero
+.option push
+.option norelax
+	la		gp, _global_pointer
+.option pop
+	# Keep the device tree pointer passed in a1, the loop below clobbers it
+	mv		s1, a1
+	# The BSS section is expected to be zero
+	la 		a0, _bss_start
+	la		a1, _bss_end
+	bgeu	a0, a1, 4f
+3:
+	sd		zero, (a0)
+	addi	a0, a0, 8
+	bltu	a0, a1, 3b
+4:
+	la		sp, _stack_end
+	li		tp, 0
+	# FPU on (FS initial), interrupts stay disabled until kinit has set up the trap frame
+	li		t0, (0b01 << 13)
+	csrw	sstatus, t0
+	# SSIE | STIE | SEIE
+	li		t0, (1 << 1) | (1 << 5) | (1 << 9)
+	csrw	sie, t0
+	la		t0, asm_trap_vector
+	csrw	stvec, t0
+	li		a0, 0
+	mv		a1, s1
+	call	kinit
+	j		1b
+
+.global _start_hart
+_start_hart:
+	# From here on the hart is known by its logical id
+	addi	a0, a0, -{FIRST_HART}
+	mv		tp, a0
+.option push
+.option norelax
+	la		gp, _global_pointer
+.option pop
+	# We divide up the stack so the harts aren't clobbering one another,
+	# kinit only starts as many harts as there are slots
+	la		sp, _stack_end
+	li		t0, {STACK_SIZE}
+	mul		t0, t0, a0
+	sub		sp, sp, t0
+	# FPU on, interrupts stay disabled until kinit enables them
+	li		t0, (0b01 << 13)
+	csrw	sstatus, t0
+	li		t0, (1 << 1) | (1 << 5) | (1 << 9)
+	csrw	sie, t0
+	la		t0, asm_trap_vector
+	csrw	stvec, t0
+	# kinit(hart_id, no device tree, hart 0 already parsed it)
+	li		a1, 0
+	call	kinit
+	j		1b
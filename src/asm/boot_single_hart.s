@@ -7,16 +7,22 @@
 .section .text.init
 .global _start
 _start:
+	# Monitor harts below FIRST_HART (see platform.rs) cannot run the firmware,
+	# they are parked for good before touching memory or the FPU
+	csrr	t0, mhartid
+	li		t1, {FIRST_HART}
+	bltu	t0, t1, 4f
 	# Any hardware threads (hart) that are not bootstrapping
 	# need to be parked
-	csrr	t0, mhartid
-	bnez	t0, 3f
+	bne		t0, t1, 3f
 	# SATP should be zero, but let's make sure
 	csrw	satp, zero
 .option push
 .option norelax
 	la		gp, _global_pointer
 .option pop
+	# Keep the device tree pointer passed in a1, the loop below clobbers it
+	mv		s1, a1
 	# The BSS section is expected to be zero
 	la 		a0, _bss_start
 	la		a1, _bss_end
@@ -38,8 +44,10 @@ _start:
     #                MPP     |  MPIE    |    MIE   |      FS   
 	li		t0, (0b11 << 11) | (1 << 7) | (1 << 3) | (0b01 << 13)
 	csrw	mstatus, t0
-    csrr	a0, mhartid
-	la		t1, main
+	# kinit gets the logical hart id, which is 0 on the boot hart
+	li		a0, 0
+	mv		a1, s1
+	la		t1, kinit
 	csrw	mepc, t1
 	la		t2, asm_trap_vector
 	csrw	mtvec, t2
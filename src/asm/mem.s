@@ -37,3 +37,9 @@ KERNEL_STACK_START: .dword _stack_start
 .global KERNEL_STACK_END
 KERNEL_STACK_END: .dword _stack_end
 
+.global SCRATCHPAD_START
+SCRATCHPAD_START: .dword _scratchpad_start
+
+.global SCRATCHPAD_END
+SCRATCHPAD_END: .dword _scratchpad_end
+
@@ -1,7 +1,84 @@
 # Modified from https://github.com/sgmarz/osblog which is copyrighted by
 # Stephen Marz and licensed under MIT.
 
-.global asm_trap_vector
+# The trap vector saves every register into the trap frame of the hart
+# (pointed to by mscratch, see trap.rs), switches to the trap stack and
+# calls m_trap, which returns the address to resume from.
+# The CSRs are those of the privilege level the firmware runs in, see
+# privilege.rs: mscratch, mepc, ... or sscratch, sepc, ... with `sbi`.
+.option norvc
+.altmacro
+.set NUM_GP_REGS, 32
+.set REG_SIZE, 8
+.set TRAP_STACK_OFFSET, 512
+.set HART_ID_OFFSET, 520
+
+.macro save_gp i, basereg=t6
+	sd	x\i, ((\i)*REG_SIZE)(\basereg)
+.endm
+.macro load_gp i, basereg=t6
+	ld	x\i, ((\i)*REG_SIZE)(\basereg)
+.endm
+.macro save_fp i, basereg=t6
+	fsd	f\i, ((NUM_GP_REGS+(\i))*REG_SIZE)(\basereg)
+.endm
+.macro load_fp i, basereg=t6
+	fld	f\i, ((NUM_GP_REGS+(\i))*REG_SIZE)(\basereg)
+.endm
 
+.section .text
+.global asm_trap_vector
+.align 4
 asm_trap_vector:
-    mret
\ No newline at end of file
+	# Swap t6 with the trap frame pointer held in mscratch
+	csrrw	t6, {SCRATCH}, t6
+	# Save x1 to x30, x0 is always zero
+	.set	i, 1
+	.rept	30
+		save_gp	%i
+		.set	i, i+1
+	.endr
+	# Save the original t6 (x31), which is now in mscratch
+	mv		t5, t6
+	csrr	t6, {SCRATCH}
+	save_gp	31, t5
+	# Put the trap frame pointer back into mscratch
+	csrw	{SCRATCH}, t5
+	# The FPU is enabled on every hart (mstatus.FS), so save the floating point registers too
+	.set	i, 0
+	.rept	32
+		save_fp	%i, t5
+		.set	i, i+1
+	.endr
+
+	# m_trap(epc, tval, cause, hart, status, frame), hart is the logical id kept in the frame
+	csrr	a0, {EPC}
+	csrr	a1, {TVAL}
+	csrr	a2, {CAUSE}
+	ld		a3, HART_ID_OFFSET(t5)
+	csrr	a4, {STATUS}
+	mv		a5, t5
+	ld		sp, TRAP_STACK_OFFSET(a5)
+	call	m_trap
+
+	# m_trap returns the new mepc
+	csrw	{EPC}, a0
+	csrr	t6, {SCRATCH}
+	.set	i, 0
+	.rept	32
+		load_fp	%i
+		.set	i, i+1
+	.endr
+	# Restore x1 to x31, t6 (x31) last since it is the base register
+	.set	i, 1
+	.rept	31
+		load_gp	%i
+		.set	i, i+1
+	.endr
+	.if {SUPERVISOR}
+	sret
+	.else
+	mret
+	.endif
+
+.noaltmacro
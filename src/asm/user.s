@@ -0,0 +1,41 @@
+# Entry to and exit from U-mode for the `user_mode` feature, see user.rs
+# enter_user(entry, arg, stack_top) saves the stack pointer in the trap
+# frame and mrets to entry(arg) in U-mode, on stack_top. entry returns to
+# user_return, whose exit syscall is turned by m_trap into a return to
+# user_exit in M-mode, with the saved stack pointer.
+# The callee-saved registers are preserved by entry itself, only ra needs
+# to be kept.
+.option norvc
+.set KERNEL_SP_OFFSET, 528
+
+.section .text
+.global enter_user
+.global user_exit
+enter_user:
+	addi	sp, sp, -16
+	sd		ra, 0(sp)
+	csrr	t0, mscratch
+	sd		sp, KERNEL_SP_OFFSET(t0)
+	csrw	mepc, a0
+	# MPP = U, and MPIE = MIE so that the interrupt state is the same on return
+	csrr	t0, mstatus
+	li		t1, (3 << 11) | (1 << 7)
+	not		t1, t1
+	and		t1, t0, t1
+	andi	t0, t0, 1 << 3
+	slli	t0, t0, 4
+	or		t0, t0, t1
+	csrw	mstatus, t0
+	mv		a0, a1
+	mv		sp, a2
+	la		ra, user_return
+	mret
+
+user_return:
+	li		a7, {SYS_EXIT}
+	ecall
+
+user_exit:
+	ld		ra, 0(sp)
+	addi	sp, sp, 16
+	ret
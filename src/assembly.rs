@@ -6,15 +6,58 @@
 // This came from the Rust book documenting global_asm!.
 // They show using include_str! with it to
 // import a full assembly file, which is what I want here.
+use crate::privilege;
 use core::arch::global_asm;
 
-#[cfg(feature = "sequential")]
-global_asm!(include_str!("asm/boot_single_hart.s"));
-#[cfg(any(
-    feature = "parallel",
-    not(any(feature = "sequential", feature = "parallel"))
+/// Modes that only run on hart 0, the other harts are never released
+pub const SINGLE_HART: bool = cfg!(any(feature = "sequential", feature = "sweep"));
+
+// in supervisor mode the harts are started by the SBI instead
+#[cfg(feature = "sbi")]
+global_asm!(
+    include_str!("asm/boot_sbi.s"),
+    FIRST_HART = const crate::platform::FIRST_HART,
+    EXT_HSM = const crate::sbi::EXT_HSM,
+    STACK_SIZE = const crate::params::STACK_SIZE
+);
+#[cfg(all(not(feature = "sbi"), any(feature = "sequential", feature = "sweep")))]
+global_asm!(
+    include_str!("asm/boot_single_hart.s"),
+    FIRST_HART = const crate::platform::FIRST_HART
+);
+#[cfg(all(
+    not(feature = "sbi"),
+    any(
+        feature = "parallel",
+        feature = "dynamic",
+        feature = "shell",
+        not(any(
+            feature = "sequential",
+            feature = "parallel",
+            feature = "dynamic",
+            feature = "sweep",
+            feature = "shell"
+        ))
+    )
 ))]
-global_asm!(include_str!("asm/boot.s"));
+global_asm!(
+    include_str!("asm/boot.s"),
+    FIRST_HART = const crate::platform::FIRST_HART,
+    STACK_SIZE = const crate::params::STACK_SIZE
+);
 
 global_asm!(include_str!("asm/mem.s"));
-global_asm!(include_str!("asm/trap.s"));
+#[cfg(feature = "user_mode")]
+global_asm!(
+    include_str!("asm/user.s"),
+    SYS_EXIT = const crate::user::SYS_EXIT
+);
+global_asm!(
+    include_str!("asm/trap.s"),
+    SCRATCH = const privilege::SCRATCH,
+    EPC = const privilege::EPC,
+    TVAL = const privilege::TVAL,
+    CAUSE = const privilege::CAUSE,
+    STATUS = const privilege::STATUS,
+    SUPERVISOR = const privilege::SUPERVISOR as u8
+);
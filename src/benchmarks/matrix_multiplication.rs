@@ -1,74 +1,343 @@
 #[cfg(feature = "sequential")]
 mod benchmark {
-    const SIDE: usize = 4;
+    const SIDE: usize = crate::params::SIDE;
+    const ITERATIONS: usize = crate::params::ITERATIONS;
     const SIZE: usize = SIDE * SIDE;
 
-    use crate::matrix::Matrix;
+    use crate::dyn_matrix::DynMatrix;
+    use crate::matrix::{Matrix, Number};
+    use crate::report::{self, Run, Timing, Verification};
     use crate::{print, println};
 
-    const A: Matrix<SIDE, SIZE, SIZE, 1> =
-        Matrix::from_slice([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]);
-    const B: Matrix<SIDE, SIZE, SIZE, 1> =
-        Matrix::from_slice([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]);
+    #[cfg_attr(feature = "scratchpad_inputs", link_section = ".scratchpad")]
+    static A: Matrix<SIDE, SIZE, SIZE, 1> = Matrix::from_indices();
+    #[cfg_attr(feature = "scratchpad_inputs", link_section = ".scratchpad")]
+    static B: Matrix<SIDE, SIZE, SIZE, 1> = Matrix::from_indices();
+
+    #[cfg_attr(feature = "scratchpad_output", link_section = ".scratchpad")]
+    static mut RESULT: Matrix<SIDE, SIZE, SIZE, 1> = Matrix::zeroes();
+
+    /// Checks `result` against the runtime-sized implementation
+    fn verify(result: &[Number]) -> Verification {
+        let a = DynMatrix::from_vec(SIDE, SIDE, A.data().to_vec());
+        let b = DynMatrix::from_vec(SIDE, SIDE, B.data().to_vec());
+        let mut reference = DynMatrix::zeroes(SIDE, SIDE);
+        reference
+            .sections_mut(SIZE)
+            .for_each(|mut section| section.multiply(&a, &b));
+        Verification::from_check(reference.data() == result)
+    }
 
     #[no_mangle]
     extern "C" fn main(hart_id: usize) {
         assert_eq!(hart_id, 0);
 
-        let mut C: Matrix<SIDE, SIZE, SIZE, 1> = Matrix::zeroes();
+        // a static rather than a local, so that it can be placed
+        let C = unsafe { &mut *core::ptr::addr_of_mut!(RESULT) };
 
-        let t = crate::time();
-        for section in C.sections_mut() {
-            section.expect("We expect this to be set").multiply(&A, &B);
+        let mut timing = Timing::new();
+        for _ in 0..ITERATIONS {
+            // the kernel accumulates into the result
+            C.clear();
+            let t = crate::time();
+            for section in C.sections_mut() {
+                let mut section = section.expect("We expect this to be set");
+                section.multiply(&A, &B);
+            }
+            timing.record(crate::time() - t);
         }
-
-        println!("Time: {:?}", crate::time() - t);
+        println!("Time: {}", timing);
         println!("Result: {:?}", C);
+        report::emit(&Run {
+            benchmark: "matrix_multiplication",
+            size: SIDE,
+            harts: 1,
+            mode: report::MODE,
+            timing,
+            verification: verify(C.data()),
+        });
     }
 }
 
 #[cfg(any(
     feature = "parallel",
-    not(any(feature = "sequential", feature = "parallel"))
+    not(any(
+        feature = "sequential",
+        feature = "parallel",
+        feature = "dynamic",
+        feature = "sweep",
+        feature = "shell"
+    ))
 ))]
 mod benchmark {
-    use crate::N_HARTS;
-    const SIDE: usize = 4;
+    const SIDE: usize = crate::params::SIDE;
+    const ITERATIONS: usize = crate::params::ITERATIONS;
     const SIZE: usize = SIDE * SIDE;
-    const SECTION_SIZE: usize = SIZE / N_HARTS;
-    const N_SECTIONS: usize = N_HARTS;
+    const N_SECTIONS: usize = 4;
+    const SECTION_SIZE: usize = SIZE / N_SECTIONS;
+    const _: () = assert!(
+        SIZE.is_multiple_of(N_SECTIONS),
+        "the matrix size must be a multiple of N_SECTIONS"
+    );
 
-    use crate::matrix::Matrix;
+    use crate::dyn_matrix::DynMatrix;
+    use crate::matrix::{Matrix, Number};
+    use crate::report::{self, Run, Timing, Verification};
     use crate::shared_matrix::SharedMatrix;
+    use crate::watchdog;
     use crate::{print, println};
+    use core::time::Duration;
 
-    const A: Matrix<SIDE, SIZE, SIZE, 1> =
-        Matrix::from_slice([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]);
-    const B: Matrix<SIDE, SIZE, SIZE, 1> =
-        Matrix::from_slice([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]);
+    #[cfg_attr(feature = "scratchpad_inputs", link_section = ".scratchpad")]
+    static A: Matrix<SIDE, SIZE, SIZE, 1> = Matrix::from_indices();
+    #[cfg_attr(feature = "scratchpad_inputs", link_section = ".scratchpad")]
+    static B: Matrix<SIDE, SIZE, SIZE, 1> = Matrix::from_indices();
 
+    #[cfg_attr(feature = "scratchpad_output", link_section = ".scratchpad")]
     static C: SharedMatrix<SIDE, SIZE, SECTION_SIZE, N_SECTIONS> =
         SharedMatrix::new(Matrix::zeroes());
 
+    /// Deadline of a single iteration
+    const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Checks `result` against the runtime-sized implementation
+    fn verify(result: &[Number]) -> Verification {
+        let a = DynMatrix::from_vec(SIDE, SIDE, A.data().to_vec());
+        let b = DynMatrix::from_vec(SIDE, SIDE, B.data().to_vec());
+        let mut reference = DynMatrix::zeroes(SIDE, SIDE);
+        reference
+            .sections_mut(SIZE)
+            .for_each(|mut section| section.multiply(&a, &b));
+        Verification::from_check(reference.data() == result)
+    }
+
     #[no_mangle]
     extern "C" fn main(hart_id: usize) {
         if hart_id == 0 {
             println!("Matrix multiplication");
+            watchdog::start(WATCHDOG_TIMEOUT * ITERATIONS as u32, &C);
         }
 
         C.initialize();
 
-        let t = crate::time(); // start timer after initialization, we will use the hart 0 timer
-        C.compute(
-            |section| {
-                section.multiply(&A, &B);
-            },
-            hart_id,
-        );
+        let mut timing = Timing::new();
+        for iteration in 0..ITERATIONS {
+            // hart 0 starts every computation after the first one
+            C.wait_round(iteration);
+            let t = crate::time(); // start timer after initialization, we will use the hart 0 timer
 
+            // sections are assigned round-robin, so any number of harts can take part
+            for section_idx in (hart_id..N_SECTIONS).step_by(crate::n_harts()) {
+                if let Err(error) = C.compute(
+                    |section| {
+                        section.multiply(&A, &B);
+                    },
+                    section_idx,
+                ) {
+                    println!(
+                        "Hart {}: cannot compute section {}: {}",
+                        hart_id, section_idx, error
+                    );
+                }
+            }
+
+            if hart_id == 0 {
+                // the time covers the whole parallel computation
+                C.wait_completed();
+                timing.record(crate::time() - t);
+                if iteration + 1 < ITERATIONS {
+                    C.reset();
+                }
+            }
+        }
+        watchdog::hart_done(hart_id);
+
+        if hart_id == 0 {
+            println!("Time: {}", timing);
+            // complete, this does not wait with the console lock held, which masks interrupts
+            let result = C.result();
+            println!("Result: {:?}", result);
+            watchdog::stop();
+
+            report::emit(&Run {
+                benchmark: "matrix_multiplication",
+                size: SIDE,
+                harts: crate::n_harts(),
+                mode: report::MODE,
+                timing,
+                verification: verify(result.data()),
+            });
+        }
+    }
+}
+
+#[cfg(feature = "dynamic")]
+mod benchmark {
+    const SIDE: usize = crate::params::SIDE;
+    const ITERATIONS: usize = crate::params::ITERATIONS;
+    const SIZE: usize = SIDE * SIDE;
+    const N_SECTIONS: usize = 8;
+    const SECTION_SIZE: usize = SIZE / N_SECTIONS;
+    const _: () = assert!(
+        SIZE.is_multiple_of(N_SECTIONS),
+        "the matrix size must be a multiple of N_SECTIONS"
+    );
+
+    use crate::dyn_matrix::DynMatrix;
+    use crate::matrix::{Matrix, Number};
+    use crate::report::{self, Run, Timing, Verification};
+    use crate::shared_matrix::SharedMatrix;
+    use crate::watchdog;
+    use crate::{print, println};
+    use core::time::Duration;
+
+    #[cfg_attr(feature = "scratchpad_inputs", link_section = ".scratchpad")]
+    static A: Matrix<SIDE, SIZE, SIZE, 1> = Matrix::from_indices();
+    #[cfg_attr(feature = "scratchpad_inputs", link_section = ".scratchpad")]
+    static B: Matrix<SIDE, SIZE, SIZE, 1> = Matrix::from_indices();
+
+    #[cfg_attr(feature = "scratchpad_output", link_section = ".scratchpad")]
+    static C: SharedMatrix<SIDE, SIZE, SECTION_SIZE, N_SECTIONS> =
+        SharedMatrix::new(Matrix::zeroes());
+
+    /// Deadline of a single iteration
+    const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Checks `result` against the runtime-sized implementation
+    fn verify(result: &[Number]) -> Verification {
+        let a = DynMatrix::from_vec(SIDE, SIDE, A.data().to_vec());
+        let b = DynMatrix::from_vec(SIDE, SIDE, B.data().to_vec());
+        let mut reference = DynMatrix::zeroes(SIDE, SIDE);
+        reference
+            .sections_mut(SIZE)
+            .for_each(|mut section| section.multiply(&a, &b));
+        Verification::from_check(reference.data() == result)
+    }
+
+    #[no_mangle]
+    extern "C" fn main(hart_id: usize) {
         if hart_id == 0 {
-            println!("Time: {:?}", crate::time() - t);
-            println!("Result: {}", C);
+            println!("Matrix multiplication (dynamic, {} sections)", N_SECTIONS);
+            watchdog::start(WATCHDOG_TIMEOUT * ITERATIONS as u32, &C);
         }
+
+        C.initialize();
+
+        let mut timing = Timing::new();
+        for iteration in 0..ITERATIONS {
+            // hart 0 starts every computation after the first one
+            C.wait_round(iteration);
+            let t = crate::time(); // start timer after initialization, we will use the hart 0 timer
+
+            if let Err(error) = C.compute_dynamic(
+                |section| {
+                    section.multiply(&A, &B);
+                },
+                hart_id,
+            ) {
+                println!("Hart {}: cannot compute: {}", hart_id, error);
+            }
+
+            if hart_id == 0 {
+                // the time covers the whole parallel computation
+                C.wait_completed();
+                timing.record(crate::time() - t);
+                if iteration + 1 < ITERATIONS {
+                    C.reset();
+                }
+            }
+        }
+        watchdog::hart_done(hart_id);
+
+        if hart_id == 0 {
+            println!("Time: {}", timing);
+            // complete, this does not wait with the console lock held, which masks interrupts
+            let result = C.result();
+            println!("Result: {:?}", result);
+            // of the last iteration
+            for hart in 0..crate::n_harts() {
+                println!("Hart {}: {} sections", hart, C.sections_processed(hart));
+            }
+            let stats = C.lock_stats();
+            println!(
+                "Section lock: {} acquisitions, {} contended",
+                stats.acquisitions, stats.contended
+            );
+            watchdog::stop();
+
+            report::emit(&Run {
+                benchmark: "matrix_multiplication",
+                size: SIDE,
+                harts: crate::n_harts(),
+                mode: report::MODE,
+                timing,
+                verification: verify(result.data()),
+            });
+        }
+    }
+}
+
+#[cfg(feature = "sweep")]
+mod benchmark {
+    use crate::dyn_matrix::DynMatrix;
+    use crate::report::{self, Run, Timing, Verification};
+    use crate::{print, println};
+
+    /// Matrix sides to run, chosen at runtime so a single image covers all of them
+    const SIDES: [usize; 5] = [4, 8, 16, 32, 64];
+
+    #[no_mangle]
+    extern "C" fn main(hart_id: usize) {
+        assert_eq!(hart_id, 0);
+        println!("Matrix multiplication (sweep)");
+
+        for side in SIDES {
+            let a = DynMatrix::from_fn(side, side, |row, col| (row * side + col) as i32);
+            let b = DynMatrix::from_fn(side, side, |row, col| (row * side + col) as i32);
+            let mut c = DynMatrix::zeroes(side, side);
+
+            let t = crate::time();
+            c.sections_mut(side * side)
+                .for_each(|mut section| section.multiply(&a, &b));
+
+            let elapsed = crate::time() - t;
+            core::hint::black_box(&c);
+            println!("Side {}: Time: {:?}", side, elapsed);
+
+            let mut timing = Timing::new();
+            timing.record(elapsed);
+            report::emit(&Run {
+                benchmark: "matrix_multiplication",
+                size: side,
+                harts: 1,
+                mode: report::MODE,
+                timing,
+                // this is the reference implementation
+                verification: Verification::Skipped,
+            });
+        }
+    }
+}
+
+#[cfg(feature = "shell")]
+pub mod benchmark {
+    use crate::dyn_matrix::{DynMatrix, DynMatrixSection};
+    use crate::shell::Benchmark;
+
+    pub static BENCHMARK: Benchmark = Benchmark {
+        name: "matrix_multiplication",
+        default_side: 16,
+        inputs,
+        kernel,
+    };
+
+    fn inputs(side: usize) -> (DynMatrix, DynMatrix) {
+        let a = DynMatrix::from_fn(side, side, |row, col| (row * side + col) as i32);
+        let b = DynMatrix::from_fn(side, side, |row, col| (row * side + col) as i32);
+        (a, b)
+    }
+
+    fn kernel(section: &mut DynMatrixSection, a: &DynMatrix, b: &DynMatrix) {
+        section.multiply(a, b);
     }
 }
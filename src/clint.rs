@@ -0,0 +1,66 @@
+// clint.rs
+// Core Local Interruptor (CLINT) driver
+// Provides the machine timer and the software interrupts (IPIs) between harts
+// The base address comes from the device tree, see machine.rs
+// Hart ids are logical, the registers are indexed by mhartid (see platform.rs)
+
+use crate::{machine, platform};
+
+const MSIP_OFFSET: usize = 0x0;
+const MTIMECMP_OFFSET: usize = 0x4000;
+const MTIME_OFFSET: usize = 0xBFF8;
+
+/// Machine software interrupt pending bit in mip/mie
+const MIP_MSIP: usize = 1 << 3;
+
+fn base() -> usize {
+    machine::get().clint_base
+}
+
+/// Current value of the machine timer, incremented at `timebase_frequency`
+pub fn mtime() -> u64 {
+    let mtime = (base() + MTIME_OFFSET) as *const u64;
+    unsafe { mtime.read_volatile() }
+}
+
+/// Programs the timer compare register of `hart_id`
+/// A timer interrupt is pending on that hart as long as `mtime >= value`
+pub fn set_mtimecmp(hart_id: usize, value: u64) {
+    let mtimecmp = (base() + MTIMECMP_OFFSET + 8 * platform::physical_hart(hart_id)) as *mut u64;
+    unsafe { mtimecmp.write_volatile(value) };
+}
+
+pub fn mtimecmp(hart_id: usize) -> u64 {
+    let mtimecmp = (base() + MTIMECMP_OFFSET + 8 * platform::physical_hart(hart_id)) as *const u64;
+    unsafe { mtimecmp.read_volatile() }
+}
+
+/// Raises a software interrupt on `hart_id`
+pub fn send_ipi(hart_id: usize) {
+    let msip = (base() + MSIP_OFFSET + 4 * platform::physical_hart(hart_id)) as *mut u32;
+    unsafe { msip.write_volatile(1) };
+}
+
+/// Clears the software interrupt of `hart_id`
+pub fn clear_ipi(hart_id: usize) {
+    let msip = (base() + MSIP_OFFSET + 4 * platform::physical_hart(hart_id)) as *mut u32;
+    unsafe { msip.write_volatile(0) };
+}
+
+/// Puts the calling hart to sleep until it receives a software interrupt
+/// MSIE must be set in mie, while mstatus.MIE should be clear so that the interrupt
+/// wakes up `wfi` without being taken; the interrupt is still pending when this returns
+pub fn wait_for_ipi() {
+    loop {
+        let mip: usize;
+        unsafe {
+            core::arch::asm!("csrr {}, mip", out(reg) mip);
+        }
+        if mip & MIP_MSIP != 0 {
+            return;
+        }
+        unsafe {
+            core::arch::asm!("wfi");
+        }
+    }
+}
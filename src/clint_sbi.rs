@@ -0,0 +1,36 @@
+// clint_sbi.rs
+// Timer and software interrupts through the SBI, replacing clint.rs with the `sbi` feature
+// (OpenSBI keeps the CLINT to machine mode)
+// The SBI only programs the timer of the calling hart, so `hart_id` must be the caller for
+// the timer functions and `clear_ipi`
+
+use crate::{platform, privilege, sbi};
+
+/// Supervisor software interrupt pending bit in sip
+const SIP_SSIP: usize = 1 << privilege::SOFTWARE_INTERRUPT;
+
+/// Current value of the time counter, incremented at `timebase_frequency`
+pub fn mtime() -> u64 {
+    let time: u64;
+    unsafe { core::arch::asm!("csrr {}, time", out(reg) time) };
+    time
+}
+
+/// Programs the timer of the calling hart
+/// A timer interrupt is pending as long as `mtime >= value`
+pub fn set_mtimecmp(hart_id: usize, value: u64) {
+    debug_assert_eq!(hart_id, crate::hart_id());
+    sbi::set_timer(value);
+}
+
+/// Raises a software interrupt on `hart_id`
+pub fn send_ipi(hart_id: usize) {
+    // fails for harts that were never started, which have nothing to be told
+    let _ = sbi::send_ipi(1, platform::physical_hart(hart_id));
+}
+
+/// Clears the software interrupt of the calling hart
+pub fn clear_ipi(hart_id: usize) {
+    debug_assert_eq!(hart_id, crate::hart_id());
+    unsafe { core::arch::asm!("csrc sip, {}", in(reg) SIP_SSIP) };
+}
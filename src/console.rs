@@ -1,32 +1,57 @@
-/* Very basic console implementation
- * Uses naive spinlock (no protection against interrupts)
- * Need to analyze what ordering should be
- * also does not work if write!() is called with arguments, is should be an easy enough fix
+/* Console shared by all the harts
+ * A whole print!/println! invocation is written while holding the console lock,
+ * so lines from different harts never interleave
+ * The lock is a SpinMutex, made re-entrant per hart (e.g. a panic or a trap handler printing
+ * while the interrupted code holds it); interrupts are disabled while it is held
+ * Lines can optionally be prefixed with the hart id and a timestamp, see `set_line_prefix`
+ * Input is received from the UART interrupt (routed by the PLIC) into a ring buffer,
+ * or polled if the interrupt has not been set up, see `read_line`
  */
 
-use crate::uart;
+use crate::ring_buffer::RingBuffer;
+use crate::serial::{self, Serial};
+use crate::spin_mutex::{LockStats, SpinMutex, SpinMutexGuard};
+use crate::trap::{self, Interrupt};
+use crate::{machine, plic};
 use core::fmt::Error;
 use core::fmt::Write;
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+const NO_OWNER: usize = usize::MAX;
+const RX_BUFFER_SIZE: usize = 256;
+
+/// What the console lock protects
+struct Output {
+    uart: Option<serial::Port>,
+    at_line_start: bool,
+}
 
 pub struct Console {
-    uart: Option<uart::Uart>,
-    locked: AtomicBool,
+    output: SpinMutex<Output>,
+    /// Id of the hart holding the lock
+    owner: AtomicUsize,
+    line_prefix: AtomicBool,
+    /// Filled by the UART interrupt handler, drained by `read_line`
+    rx_buffer: RingBuffer<RX_BUFFER_SIZE>,
+    rx_interrupts: AtomicBool,
 }
 
-static mut CONSOLE: Console = {
-    Console {
+static CONSOLE: Console = Console {
+    output: SpinMutex::new(Output {
         uart: None,
-        locked: AtomicBool::new(false),
-    }
+        at_line_start: true,
+    }),
+    owner: AtomicUsize::new(NO_OWNER),
+    line_prefix: AtomicBool::new(false),
+    rx_buffer: RingBuffer::new(),
+    rx_interrupts: AtomicBool::new(false),
 };
 
 #[macro_export]
 macro_rules! print {
     ($($args:tt)+) => ({
-        use core::fmt::Write;
-        let _ = write!(unsafe {crate::console::Console::get()}, $($args)+);
-        });
+        $crate::console::_print(format_args!($($args)+));
+    });
 }
 
 #[macro_export]
@@ -43,62 +68,192 @@ macro_rules! println
     });
 }
 
+#[doc(hidden)]
+pub fn _print(args: core::fmt::Arguments) {
+    let _ = Console::get().lock().write_fmt(args);
+}
+
+/// Enables or disables the `[hart time]` prefix at the start of every line
+/// It is enabled at boot with the `line_prefix` feature
+pub fn set_line_prefix(enabled: bool) {
+    CONSOLE.line_prefix.store(enabled, Ordering::SeqCst);
+}
+
+pub fn line_prefix() -> bool {
+    CONSOLE.line_prefix.load(Ordering::SeqCst)
+}
+
 impl Console {
-    pub unsafe fn get() -> &'static mut Self {
-        if CONSOLE.uart.is_none() {
-            while CONSOLE
-                .locked
-                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
-                .is_err()
-            {
-                // spin
-            }
-            // lock acquired
-            assert_eq!(
-                CONSOLE.locked.load(Ordering::SeqCst),
-                true,
-                "lock not held before release"
-            );
-            // check again since we read without aquiring the lock
-            if CONSOLE.uart.is_none() {
-                let mut uart = uart::Uart::new(0x1000_0000);
-                // need to understand what init does
-                uart.init();
-                CONSOLE.uart = Some(uart);
+    pub fn get() -> &'static Self {
+        &CONSOLE
+    }
+
+    /// Locks the console for the calling hart, the lock is released when the guard is dropped
+    pub fn lock(&self) -> ConsoleGuard<'_> {
+        let hart_id = crate::hart_id();
+        // the hart already holds the lock, so its interrupts are masked: either the holder
+        // trapped or it is printing a panic, and the outer guard is suspended meanwhile
+        let output = if self.owner.load(Ordering::Relaxed) == hart_id {
+            None
+        } else {
+            let output = self.output.lock();
+            self.owner.store(hart_id, Ordering::Relaxed);
+            Some(output)
+        };
+        let mut guard = ConsoleGuard {
+            console: self,
+            hart_id,
+            output,
+        };
+        // the UART is initialized by the first hart that prints
+        let output = guard.output();
+        if output.uart.is_none() {
+            let mut uart = serial::Port::new(machine::get(), serial::DEFAULT_BAUD_RATE);
+            uart.init();
+            #[cfg(feature = "uart_buffered_tx")]
+            uart.enable_buffered_tx();
+            output.uart = Some(uart);
+        }
+        guard
+    }
+
+    /// Counters of the console lock
+    pub fn lock_stats(&self) -> LockStats {
+        self.output.stats()
+    }
+
+    /// Routes the UART interrupt to `hart_id` through the PLIC
+    /// From then on input is buffered by the interrupt handler instead of being polled,
+    /// unless the console can only be polled (see `Serial::interrupt_driven`)
+    pub fn init_interrupts(&self, hart_id: usize) {
+        if !self.lock().uart().interrupt_driven() {
+            return;
+        }
+        let irq = machine::get().uart_irq;
+        if let Err(plic::InvalidIrq(irq)) =
+            plic::register_handler(irq, |_| Console::get().handle_interrupt())
+        {
+            // the console stays polled
+            crate::println!("UART interrupt {} is out of range, input is polled", irq);
+            return;
+        }
+        trap::register_handler(Interrupt::External, plic::handle_interrupt);
+        plic::set_priority(irq, 1);
+        plic::set_threshold(hart_id, 0);
+        plic::enable(hart_id, irq);
+        self.rx_interrupts.store(true, Ordering::SeqCst);
+    }
+
+    /// Services an interrupt of the console UART
+    pub fn handle_interrupt(&self) {
+        let mut guard = self.lock();
+        let uart = guard.uart();
+        while let Some(c) = uart.get() {
+            // characters received while the buffer is full are dropped
+            self.rx_buffer.push(c);
+        }
+        uart.handle_interrupt();
+    }
+
+    /// Returns the next input character, without waiting
+    pub fn get_char(&self) -> Option<u8> {
+        if self.rx_interrupts.load(Ordering::SeqCst) {
+            self.rx_buffer.pop()
+        } else {
+            self.lock().uart().get()
+        }
+    }
+
+    /// Reads a line into `buffer`, echoing it, and returns it without the line terminator
+    /// Blocks until return is pressed; input that does not fit in `buffer` is discarded
+    pub fn read_line<'b>(&self, buffer: &'b mut [u8]) -> &'b str {
+        let mut len = 0;
+        loop {
+            let Some(c) = self.get_char() else {
+                core::hint::spin_loop();
+                continue;
+            };
+            match c {
+                b'\r' | b'\n' => {
+                    crate::println!();
+                    break;
+                }
+                // backspace and delete
+                8 | 127 if len > 0 => {
+                    len -= 1;
+                    crate::print!("\x08 \x08");
+                }
+                c if c.is_ascii() && !c.is_ascii_control() && len < buffer.len() => {
+                    buffer[len] = c;
+                    len += 1;
+                    crate::print!("{}", c as char);
+                }
+                _ => {}
             }
-            assert_eq!(
-                CONSOLE.locked.load(Ordering::SeqCst),
-                true,
-                "lock not held before release"
-            );
-            CONSOLE.locked.store(false, Ordering::Release);
         }
-        &mut CONSOLE
+        // only printable ASCII is stored
+        core::str::from_utf8(&buffer[..len]).unwrap_or("")
+    }
+
+    /// Waits until all the buffered output has been sent
+    pub fn flush(&self) {
+        self.lock().uart().flush();
     }
 }
 
-impl Write for Console {
+pub struct ConsoleGuard<'a> {
+    console: &'a Console,
+    hart_id: usize,
+    /// None if the hart re-entered the lock, the outermost guard releases it
+    output: Option<SpinMutexGuard<'a, Output>>,
+}
+
+impl ConsoleGuard<'_> {
+    fn output(&mut self) -> &mut Output {
+        match self.output.as_mut() {
+            Some(output) => output,
+            None => unsafe { &mut *self.console.output.data_ptr() },
+        }
+    }
+
+    fn uart(&mut self) -> &mut serial::Port {
+        self.output()
+            .uart
+            .as_mut()
+            .expect("initialized when locking")
+    }
+}
+
+impl Write for ConsoleGuard<'_> {
     fn write_str(&mut self, out: &str) -> Result<(), Error> {
-        while self
-            .locked
-            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
-            .is_err()
-        {
-            // write!(self.uart.as_mut().unwrap(), "locked").unwrap();
+        let hart_id = self.hart_id;
+        let line_prefix = self.console.line_prefix.load(Ordering::Relaxed);
+        let output = self.output();
+        let at_line_start = &mut output.at_line_start;
+        let uart = output.uart.as_mut().expect("initialized when locking");
+        for line in out.split_inclusive('\n') {
+            if line_prefix && *at_line_start {
+                let time = crate::time();
+                write!(
+                    uart,
+                    "[{} {:>4}.{:06}] ",
+                    hart_id,
+                    time.as_secs(),
+                    time.subsec_micros()
+                )?;
+            }
+            uart.write_str(line)?;
+            *at_line_start = line.ends_with('\n');
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ConsoleGuard<'_> {
+    fn drop(&mut self) {
+        // before `output` releases the lock
+        if self.output.is_some() {
+            self.console.owner.store(NO_OWNER, Ordering::Relaxed);
         }
-        assert_eq!(
-            self.locked.load(Ordering::SeqCst),
-            true,
-            "lock not held before release"
-        );
-        let res = write!(self.uart.as_mut().unwrap(), "{}", out);
-        assert_eq!(
-            self.locked.load(Ordering::SeqCst),
-            true,
-            "lock not held before release"
-        );
-        self.locked.store(false, Ordering::Release);
-        // write!(self.uart, "lock released");
-        res
     }
 }
@@ -0,0 +1,127 @@
+// dyn_matrix.rs
+// Runtime-sized counterpart of Matrix/MatrixSection, backed by the heap
+// Sections are chunks of consecutive elements (in row-major order) like for MatrixSection,
+// and the kernels accumulate into them with the same semantics
+
+use crate::matrix::Number;
+use alloc::vec;
+use alloc::vec::Vec;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DynMatrix {
+    data: Vec<Number>,
+    rows: usize,
+    cols: usize,
+}
+
+impl DynMatrix {
+    pub fn zeroes(rows: usize, cols: usize) -> Self {
+        DynMatrix {
+            data: vec![0; rows * cols],
+            rows,
+            cols,
+        }
+    }
+
+    pub fn from_vec(rows: usize, cols: usize, data: Vec<Number>) -> Self {
+        assert_eq!(
+            data.len(),
+            rows * cols,
+            "data does not match the matrix size"
+        );
+        DynMatrix { data, rows, cols }
+    }
+
+    /// Builds the matrix calling `f(row, col)` for every element
+    pub fn from_fn(rows: usize, cols: usize, f: impl Fn(usize, usize) -> Number) -> Self {
+        let data = (0..rows * cols).map(|i| f(i / cols, i % cols)).collect();
+        DynMatrix { data, rows, cols }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn data(&self) -> &[Number] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [Number] {
+        &mut self.data
+    }
+
+    /// Splits the matrix in sections of `section_size` elements, the last one can be shorter
+    pub fn sections_mut(
+        &mut self,
+        section_size: usize,
+    ) -> impl Iterator<Item = DynMatrixSection<'_>> {
+        let (rows, cols) = (self.rows, self.cols);
+        self.data
+            .chunks_mut(section_size)
+            .enumerate()
+            .map(move |(i, section)| DynMatrixSection::new(section, rows, cols, i * section_size))
+    }
+}
+
+#[derive(Debug)]
+pub struct DynMatrixSection<'a> {
+    section_data: &'a mut [Number],
+    rows: usize,
+    cols: usize,
+    /// Index in the whole matrix of the first element of the section
+    offset: usize,
+}
+
+impl<'a> DynMatrixSection<'a> {
+    pub fn new(section_data: &'a mut [Number], rows: usize, cols: usize, offset: usize) -> Self {
+        DynMatrixSection {
+            section_data,
+            rows,
+            cols,
+            offset,
+        }
+    }
+
+    pub fn multiply(&mut self, a: &DynMatrix, b: &DynMatrix) {
+        assert_eq!(a.cols, b.rows, "incompatible matrices");
+        self.section_data
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, elem)| {
+                let idx = self.offset + i;
+                let row = idx / self.cols;
+                let col = idx % self.cols;
+                for k in 0..a.cols {
+                    *elem += a.data[row * a.cols + k] * b.data[k * b.cols + col];
+                }
+            });
+    }
+
+    pub fn convolute(&mut self, a: &DynMatrix, kernel: &DynMatrix) {
+        let kernel_y_radius = (kernel.rows - 1) / 2;
+        let kernel_x_radius = (kernel.cols - 1) / 2;
+        self.section_data
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, elem)| {
+                let idx = self.offset + i;
+                let row = idx / self.cols;
+                let col = idx % self.cols;
+                for k in 0..kernel.rows {
+                    for l in 0..kernel.cols {
+                        let y = (row + k) as isize - kernel_y_radius as isize;
+                        let x = (col + l) as isize - kernel_x_radius as isize;
+                        if (y >= 0 && y < self.rows as isize) && (x >= 0 && x < self.cols as isize)
+                        {
+                            *elem += a.data[y as usize * self.cols + x as usize]
+                                * kernel.data[k * kernel.cols + l];
+                        }
+                    }
+                }
+            });
+    }
+}
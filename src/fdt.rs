@@ -0,0 +1,218 @@
+// fdt.rs
+// Minimal flattened device tree (DTB) parser
+// Only what is needed to walk the structure block and read properties,
+// see the devicetree specification (v0.4, chapter 5) for the format
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_HEADER_SIZE: usize = 40;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+/// Maximum node depth that the tree walkers keep track of
+pub const MAX_DEPTH: usize = 16;
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+/// Reads a value made of `cells` 32 bit big endian cells (e.g. an address in a `reg` property)
+pub fn read_cells(data: &[u8], cells: usize) -> Option<u64> {
+    (0..cells).try_fold(0u64, |acc, i| {
+        Some((acc << 32) | read_u32(data, i * 4)? as u64)
+    })
+}
+
+/// Reads a null terminated string starting at `offset`
+fn read_str(data: &[u8], offset: usize) -> Option<&str> {
+    let bytes = data.get(offset..)?;
+    let len = bytes.iter().position(|&b| b == 0)?;
+    core::str::from_utf8(&bytes[..len]).ok()
+}
+
+const fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+#[derive(Clone, Copy)]
+pub struct Fdt<'a> {
+    blob: &'a [u8],
+    structs: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl Fdt<'static> {
+    /// # Safety
+    /// `ptr` must either be null or point to memory that stays valid and unmodified
+    /// for the rest of the execution (e.g. the blob QEMU places at the end of RAM)
+    pub unsafe fn from_ptr(ptr: *const u8) -> Option<Self> {
+        if ptr.is_null() || !(ptr as usize).is_multiple_of(4) {
+            return None;
+        }
+        let header = core::slice::from_raw_parts(ptr, FDT_HEADER_SIZE);
+        if read_u32(header, 0)? != FDT_MAGIC {
+            return None;
+        }
+        let total_size = read_u32(header, 4)? as usize;
+        Self::new(core::slice::from_raw_parts(ptr, total_size))
+    }
+}
+
+impl<'a> Fdt<'a> {
+    pub fn new(blob: &'a [u8]) -> Option<Self> {
+        if read_u32(blob, 0)? != FDT_MAGIC {
+            return None;
+        }
+        let struct_offset = read_u32(blob, 8)? as usize;
+        let strings_offset = read_u32(blob, 12)? as usize;
+        let strings_size = read_u32(blob, 32)? as usize;
+        let struct_size = read_u32(blob, 36)? as usize;
+        Some(Fdt {
+            blob,
+            structs: blob.get(struct_offset..struct_offset + struct_size)?,
+            strings: blob.get(strings_offset..strings_offset + strings_size)?,
+        })
+    }
+
+    /// The whole blob, as given by the totalsize field of the header
+    pub fn blob(&self) -> &'a [u8] {
+        self.blob
+    }
+
+    /// Iterates over all the nodes of the tree in depth first order
+    pub fn nodes(&self) -> Nodes<'a> {
+        Nodes {
+            fdt: *self,
+            offset: 0,
+            depth: 0,
+        }
+    }
+
+    /// Skips NOP tokens and returns the next token with its offset
+    fn token(&self, mut offset: usize) -> Option<(u32, usize)> {
+        loop {
+            let token = read_u32(self.structs, offset)?;
+            if token != FDT_NOP {
+                return Some((token, offset));
+            }
+            offset += 4;
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Node<'a> {
+    /// Node name including the unit address (e.g. `uart@10000000`), empty for the root
+    pub name: &'a str,
+    /// 0 for the root node
+    pub depth: usize,
+    fdt: Fdt<'a>,
+    /// Offset of the first token after the node name
+    props_offset: usize,
+}
+
+impl<'a> Node<'a> {
+    pub fn properties(&self) -> Properties<'a> {
+        Properties {
+            fdt: self.fdt,
+            offset: self.props_offset,
+        }
+    }
+
+    pub fn property(&self, name: &str) -> Option<&'a [u8]> {
+        self.properties()
+            .find(|(prop_name, _)| *prop_name == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn property_u32(&self, name: &str) -> Option<u32> {
+        read_u32(self.property(name)?, 0)
+    }
+
+    /// Returns the value of a string property, without the null terminator
+    pub fn property_str(&self, name: &str) -> Option<&'a str> {
+        read_str(self.property(name)?, 0)
+    }
+
+    /// Checks if one of the entries of the `compatible` string list is `compatible`
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.property("compatible").is_some_and(|list| {
+            list.split(|&b| b == 0)
+                .any(|entry| entry == compatible.as_bytes())
+        })
+    }
+
+    /// Name without the unit address (e.g. `uart` for `uart@10000000`)
+    pub fn base_name(&self) -> &'a str {
+        self.name.split('@').next().unwrap_or(self.name)
+    }
+}
+
+pub struct Nodes<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+    depth: usize,
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (token, offset) = self.fdt.token(self.offset)?;
+            match token {
+                FDT_BEGIN_NODE => {
+                    let name = read_str(self.fdt.structs, offset + 4)?;
+                    let props_offset = align4(offset + 4 + name.len() + 1);
+                    let node = Node {
+                        name,
+                        depth: self.depth,
+                        fdt: self.fdt,
+                        props_offset,
+                    };
+                    self.offset = props_offset;
+                    self.depth += 1;
+                    return Some(node);
+                }
+                FDT_END_NODE => {
+                    self.depth = self.depth.checked_sub(1)?;
+                    self.offset = offset + 4;
+                }
+                FDT_PROP => {
+                    let len = read_u32(self.fdt.structs, offset + 4)? as usize;
+                    self.offset = align4(offset + 12 + len);
+                }
+                FDT_END => return None,
+                // malformed structure block
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// Iterates over the properties of a single node as (name, value) pairs
+pub struct Properties<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+}
+
+impl<'a> Iterator for Properties<'a> {
+    type Item = (&'a str, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (token, offset) = self.fdt.token(self.offset)?;
+        if token != FDT_PROP {
+            // properties always come before the child nodes
+            return None;
+        }
+        let len = read_u32(self.fdt.structs, offset + 4)? as usize;
+        let name_offset = read_u32(self.fdt.structs, offset + 8)? as usize;
+        let value = self.fdt.structs.get(offset + 12..offset + 12 + len)?;
+        self.offset = align4(offset + 12 + len);
+        Some((read_str(self.fdt.strings, name_offset)?, value))
+    }
+}
@@ -0,0 +1,186 @@
+// heap.rs
+// Global allocator over the free RAM after the kernel stacks (_heap_start in the linker script)
+// up to the end of the memory reported by the device tree, or to the scratchpad region of the
+// linker script if it is carved out of the RAM
+// First fit over an address ordered free list, adjacent free blocks are merged on dealloc
+// Every block is BLOCK_ALIGN aligned and a multiple of BLOCK_ALIGN long, so the
+// free block header always fits in what is left over after an allocation
+
+use crate::{machine, trap};
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+const BLOCK_ALIGN: usize = 16;
+
+const fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+struct Heap {
+    /// Sentinel, `head.next` is the free block with the lowest address
+    head: FreeBlock,
+    size: usize,
+    free: usize,
+}
+
+impl Heap {
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let size = align_up(layout.size().max(1), BLOCK_ALIGN);
+        let align = layout.align().max(BLOCK_ALIGN);
+        let mut prev: *mut FreeBlock = &mut self.head;
+        while !(*prev).next.is_null() {
+            let block = (*prev).next;
+            let block_start = block as usize;
+            let block_end = block_start + (*block).size;
+            let start = align_up(block_start, align);
+            let end = start + size;
+            if end <= block_end {
+                let next = (*block).next;
+                // what is left after the allocation stays free
+                let after = if end < block_end {
+                    let tail = end as *mut FreeBlock;
+                    tail.write(FreeBlock {
+                        size: block_end - end,
+                        next,
+                    });
+                    tail
+                } else {
+                    next
+                };
+                // and so does the padding before it
+                if start > block_start {
+                    (*block).size = start - block_start;
+                    (*block).next = after;
+                } else {
+                    (*prev).next = after;
+                }
+                self.free -= size;
+                return start as *mut u8;
+            }
+            prev = block;
+        }
+        core::ptr::null_mut()
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let size = align_up(layout.size().max(1), BLOCK_ALIGN);
+        let start = ptr as usize;
+        let head: *mut FreeBlock = &mut self.head;
+        let mut prev = head;
+        while !(*prev).next.is_null() && ((*prev).next as usize) < start {
+            prev = (*prev).next;
+        }
+        let next = (*prev).next;
+        let block = ptr as *mut FreeBlock;
+        block.write(FreeBlock { size, next });
+        if !next.is_null() && start + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+        if prev != head && prev as usize + (*prev).size == start {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            (*prev).next = block;
+        }
+        self.free += size;
+    }
+}
+
+pub struct LockedHeap {
+    locked: AtomicBool,
+    heap: UnsafeCell<Heap>,
+}
+
+impl LockedHeap {
+    const fn empty() -> Self {
+        LockedHeap {
+            locked: AtomicBool::new(false),
+            heap: UnsafeCell::new(Heap {
+                head: FreeBlock {
+                    size: 0,
+                    next: core::ptr::null_mut(),
+                },
+                size: 0,
+                free: 0,
+            }),
+        }
+    }
+
+    /// Runs `f` with exclusive access to the heap
+    /// Interrupts are disabled meanwhile, so a handler on the same hart cannot deadlock on the lock
+    fn with_heap<R>(&self, f: impl FnOnce(&mut Heap) -> R) -> R {
+        let interrupts = trap::disable_interrupts();
+        while self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        let res = f(unsafe { &mut *self.heap.get() });
+        self.locked.store(false, Ordering::Release);
+        trap::restore_interrupts(interrupts);
+        res
+    }
+}
+
+unsafe impl Sync for LockedHeap {}
+
+unsafe impl GlobalAlloc for LockedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.with_heap(|heap| heap.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.with_heap(|heap| heap.dealloc(ptr, layout))
+    }
+}
+
+#[global_allocator]
+static HEAP: LockedHeap = LockedHeap::empty();
+
+/// Hands the free RAM to the allocator, allocations fail until this is called
+/// # Safety
+/// Must be called once, after `machine::init`, before any allocation
+pub unsafe fn init() {
+    extern "C" {
+        static HEAP_START: usize;
+        static SCRATCHPAD_START: usize;
+    }
+    let machine = machine::get();
+    let start = align_up(HEAP_START, BLOCK_ALIGN);
+    let mut end = machine.memory_start + machine.memory_size;
+    if SCRATCHPAD_START >= start {
+        end = end.min(SCRATCHPAD_START);
+    }
+    // QEMU places the device tree at the end of RAM
+    if let Some((dtb_start, _)) = machine.dtb.filter(|&(dtb_start, _)| dtb_start >= start) {
+        end = end.min(dtb_start);
+    }
+    let end = end & !(BLOCK_ALIGN - 1);
+    if end <= start {
+        return;
+    }
+    HEAP.with_heap(|heap| {
+        let block = start as *mut FreeBlock;
+        block.write(FreeBlock {
+            size: end - start,
+            next: core::ptr::null_mut(),
+        });
+        heap.head.next = block;
+        heap.size = end - start;
+        heap.free = end - start;
+    });
+}
+
+/// (total, free) size of the heap in bytes
+pub fn stats() -> (usize, usize) {
+    HEAP.with_heap(|heap| (heap.size, heap.free))
+}
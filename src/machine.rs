@@ -0,0 +1,188 @@
+// machine.rs
+// Hardware configuration discovered from the device tree at boot
+// Falls back to the defaults of the platform if no valid device tree is passed in a1
+
+use crate::fdt::{self, Fdt};
+use crate::platform;
+use crate::serial;
+use core::fmt::Display;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Upper bound on the number of harts, each one gets a `params::STACK_SIZE` slot of the stack
+/// region of the linker script
+pub const MAX_HARTS: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Machine {
+    pub n_harts: usize,
+    pub memory_start: usize,
+    pub memory_size: usize,
+    pub uart_base: usize,
+    /// Register layout of the UART, which selects the console driver
+    pub uart_kind: serial::Kind,
+    /// Input clock of the UART, used to compute the baud rate divisor
+    pub uart_clock: u32,
+    /// PLIC interrupt source of the UART
+    pub uart_irq: u32,
+    pub clint_base: usize,
+    pub plic_base: usize,
+    /// QEMU test finisher, used to exit the emulator (see shutdown.rs)
+    pub test_base: Option<usize>,
+    /// Frequency of the CLINT mtime counter
+    pub timebase_frequency: u64,
+    /// false if the defaults are in use because no valid device tree was found
+    pub from_device_tree: bool,
+    /// (start, end) of the device tree blob, which must not be overwritten (e.g. by the heap)
+    pub dtb: Option<(usize, usize)>,
+}
+
+impl Machine {
+    /// Collects the configuration from the device tree
+    /// Anything that is not found keeps its platform default
+    pub fn from_device_tree(fdt: &Fdt) -> Self {
+        let mut n_harts: usize = 0;
+        let mut memory = None;
+        let mut uart_base = None;
+        let mut uart_kind = None;
+        let mut uart_clock = None;
+        let mut uart_irq = None;
+        let mut clint_base = None;
+        let mut plic_base = None;
+        let mut test_base = None;
+        let mut timebase_frequency = None;
+
+        // (#address-cells, #size-cells) that each node defines for its children
+        let mut cells = [(2, 1); fdt::MAX_DEPTH];
+        for node in fdt.nodes() {
+            if node.depth >= fdt::MAX_DEPTH {
+                continue;
+            }
+            let (address_cells, size_cells) = match node.depth {
+                0 => (2, 1),
+                depth => cells[depth - 1],
+            };
+            cells[node.depth] = (
+                node.property_u32("#address-cells")
+                    .map_or(2, |c| c as usize),
+                node.property_u32("#size-cells").map_or(1, |c| c as usize),
+            );
+            let reg = node.property("reg");
+            let reg_address = reg.and_then(|reg| fdt::read_cells(reg, address_cells));
+
+            match node.property_str("device_type") {
+                Some("cpu") if node.property_str("status") != Some("disabled") => {
+                    n_harts += 1;
+                    timebase_frequency =
+                        timebase_frequency.or_else(|| node.property_u32("timebase-frequency"));
+                }
+                Some("memory") if memory.is_none() => {
+                    let size = reg
+                        .and_then(|reg| fdt::read_cells(reg.get(address_cells * 4..)?, size_cells));
+                    memory = reg_address.zip(size);
+                }
+                _ => {}
+            }
+            if node.depth == 1 && node.base_name() == "cpus" {
+                // the frequency is usually set on /cpus and is shared by all the cpu nodes
+                timebase_frequency = node.property_u32("timebase-frequency");
+            }
+            let kind = serial::Kind::ALL
+                .into_iter()
+                .find(|kind| node.is_compatible(kind.compatible()));
+            if uart_base.is_none() && kind.is_some() {
+                uart_base = reg_address;
+                uart_kind = kind;
+                uart_clock = node.property_u32("clock-frequency");
+                uart_irq = node.property_u32("interrupts");
+            }
+            if clint_base.is_none()
+                && (node.is_compatible("riscv,clint0") || node.is_compatible("sifive,clint0"))
+            {
+                clint_base = reg_address;
+            }
+            if plic_base.is_none()
+                && (node.is_compatible("riscv,plic0") || node.is_compatible("sifive,plic-1.0.0"))
+            {
+                plic_base = reg_address;
+            }
+            if test_base.is_none() && node.is_compatible("sifive,test0") {
+                test_base = reg_address;
+            }
+        }
+
+        let default = platform::DEFAULT_MACHINE;
+        let (memory_start, memory_size) =
+            memory.unwrap_or((default.memory_start as u64, default.memory_size as u64));
+        Machine {
+            // the monitor harts are cpu nodes too
+            n_harts: match n_harts.saturating_sub(platform::FIRST_HART) {
+                0 => default.n_harts,
+                n_harts => n_harts,
+            },
+            memory_start: memory_start as usize,
+            memory_size: memory_size as usize,
+            uart_base: uart_base.map_or(default.uart_base, |base| base as usize),
+            uart_kind: uart_kind.unwrap_or(default.uart_kind),
+            uart_clock: uart_clock.unwrap_or(default.uart_clock),
+            uart_irq: uart_irq.unwrap_or(default.uart_irq),
+            clint_base: clint_base.map_or(default.clint_base, |base| base as usize),
+            plic_base: plic_base.map_or(default.plic_base, |base| base as usize),
+            test_base: test_base.map(|base| base as usize),
+            timebase_frequency: timebase_frequency
+                .map_or(default.timebase_frequency, |freq| freq as u64),
+            from_device_tree: true,
+            dtb: None,
+        }
+    }
+}
+
+impl Display for Machine {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} harts, {} MiB RAM at {:#x}, {} UART at {:#x}, CLINT at {:#x}, PLIC at {:#x}, timebase {} Hz{}",
+            self.n_harts,
+            self.memory_size / (1024 * 1024),
+            self.memory_start,
+            self.uart_kind.as_str(),
+            self.uart_base,
+            self.clint_base,
+            self.plic_base,
+            self.timebase_frequency,
+            if self.from_device_tree {
+                ""
+            } else {
+                " (defaults, no device tree)"
+            }
+        )
+    }
+}
+
+static mut MACHINE: Machine = platform::DEFAULT_MACHINE;
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Parses the device tree pointed to by `dtb` and publishes the result to the other harts
+/// # Safety
+/// Must be called once, by a single hart, before any other hart calls `wait_ready`
+pub unsafe fn init(dtb: usize) {
+    if let Some(fdt) = Fdt::from_ptr(dtb as *const u8) {
+        let blob = fdt.blob().as_ptr_range();
+        *core::ptr::addr_of_mut!(MACHINE) = Machine {
+            dtb: Some((blob.start as usize, blob.end as usize)),
+            ..Machine::from_device_tree(&fdt)
+        };
+    }
+    READY.store(true, Ordering::Release);
+}
+
+/// Spins until the hart running `init` has published the configuration
+pub fn wait_ready() {
+    while !READY.load(Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
+}
+
+/// Returns the discovered configuration (the defaults before `init` completes)
+pub fn get() -> &'static Machine {
+    unsafe { &*core::ptr::addr_of!(MACHINE) }
+}
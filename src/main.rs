@@ -1,18 +1,26 @@
 #![no_std]
 #![no_main]
 
-const N_HARTS: usize = 4;
+extern crate alloc;
 
 use core::panic::PanicInfo;
-// right now panic only halts one hart => to change this we should use some interrupt probably
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
-    abort();
+    trap::disable_interrupts();
+    if !shutdown::request_stop() {
+        // another hart is already reporting its failure and stopping everyone
+        abort();
+    }
+    let hart_id = hart_id();
+    println!("Hart {} {}", hart_id, info);
+    shutdown::stop_other_harts(hart_id);
+    shutdown::exit_failure(shutdown::EXIT_PANIC);
 }
 
 #[no_mangle]
 extern "C" fn abort() -> ! {
+    trap::disable_interrupts();
     loop {
         unsafe {
             core::arch::asm!("wfi");
@@ -20,6 +28,7 @@ extern "C" fn abort() -> ! {
     }
 }
 
+#[cfg(not(feature = "shell"))]
 #[cfg_attr(
     feature = "matrix_multiplication",
     path = "benchmarks/matrix_multiplication.rs"
@@ -30,16 +39,161 @@ extern "C" fn abort() -> ! {
 )]
 mod benchmark;
 
+// the shell offers every benchmark
+#[cfg(feature = "shell")]
+#[path = "benchmarks/convolution.rs"]
+mod convolution;
+#[cfg(feature = "shell")]
+#[path = "benchmarks/matrix_multiplication.rs"]
+mod matrix_multiplication;
+#[cfg(feature = "shell")]
+pub mod shell;
+
 pub mod assembly;
+#[cfg_attr(feature = "sbi", path = "clint_sbi.rs")]
+pub mod clint;
 pub mod console;
+pub mod fdt;
+pub mod heap;
+pub mod machine;
+#[cfg(feature = "paging")]
+pub mod paging;
+pub mod params;
+pub mod platform;
+pub mod plic;
+#[cfg(feature = "user_mode")]
+pub mod pmp;
+pub mod privilege;
+pub mod report;
+pub mod ring_buffer;
+#[cfg(feature = "sbi")]
+pub mod sbi;
+pub mod serial;
+pub mod shutdown;
+pub mod sifive_uart;
+pub mod spin_mutex;
+pub mod stack;
+pub mod trap;
 pub mod uart;
+#[cfg(feature = "user_mode")]
+pub mod user;
+pub mod watchdog;
 
+pub mod dyn_matrix;
 pub mod matrix;
 pub mod shared_matrix;
 
-// move to a CLINT module
+extern "C" {
+    /// Entry point of the selected benchmark
+    fn main(hart_id: usize);
+}
+
+/// Called by boot.s on every application hart with its logical id, `dtb` is the device tree
+/// pointer the firmware passes in a1
+/// Hart 0 discovers the machine configuration and then releases the worker harts with an IPI
+/// (or starts them through the SBI), the others stay parked until they are released
+#[no_mangle]
+extern "C" fn kinit(hart_id: usize, dtb: usize) {
+    unsafe { trap::init_hart(hart_id) };
+    if hart_id == 0 {
+        // boot.s leaves interrupts disabled until the trap frame is in place
+        trap::enable_interrupts();
+        unsafe {
+            stack::init();
+            machine::init(dtb);
+            heap::init();
+        }
+        clint::set_mtimecmp(hart_id, u64::MAX);
+        // the timestamp needs the timebase frequency of the machine
+        #[cfg(feature = "line_prefix")]
+        console::set_line_prefix(true);
+        println!("Platform: {}", platform::NAME);
+        println!("Machine: {}", machine::get());
+        println!("Heap: {} KiB", heap::stats().0 / 1024);
+        #[cfg(feature = "paging")]
+        unsafe {
+            let tables = paging::init();
+            paging::enable();
+            println!(
+                "Paging: Sv39, {} pages, {} KiB of page tables",
+                report::PAGING,
+                tables * 4
+            );
+        }
+        console::Console::get().init_interrupts(hart_id);
+        #[cfg(not(feature = "sbi"))]
+        for hart in 1..n_harts() {
+            clint::send_ipi(hart);
+        }
+        #[cfg(feature = "sbi")]
+        for hart in (1..n_harts()).filter(|_| !assembly::SINGLE_HART) {
+            if let Err(error) = sbi::start_hart(hart) {
+                println!("Cannot start hart {}: {:?}", hart, error);
+            }
+        }
+    } else {
+        // harts that are not needed are never released
+        #[cfg(not(feature = "sbi"))]
+        clint::wait_for_ipi();
+        if shutdown::stop_requested() {
+            abort();
+        }
+        machine::wait_ready();
+        #[cfg(feature = "paging")]
+        unsafe {
+            paging::enable()
+        };
+        clint::clear_ipi(hart_id);
+        clint::set_mtimecmp(hart_id, u64::MAX);
+        trap::enable_interrupts();
+    }
+    #[cfg(feature = "user_mode")]
+    user::ecall_benchmark(hart_id);
+    unsafe { main(hart_id) };
+    if hart_id == 0 {
+        stack::report();
+    }
+    // scripted runs (see the runner crate) stop QEMU once the benchmark is done
+    #[cfg(feature = "exit_when_done")]
+    if hart_id == 0 {
+        shutdown::exit_success();
+    }
+}
+
+/// Logical id of the current hart, see platform.rs
+#[cfg(not(feature = "sbi"))]
+pub fn hart_id() -> usize {
+    let mhartid: usize;
+    unsafe { core::arch::asm!("csrr {}, mhartid", out(reg) mhartid) };
+    mhartid - platform::FIRST_HART
+}
+
+/// Logical id of the current hart, kept in tp by boot_sbi.s
+#[cfg(feature = "sbi")]
+pub fn hart_id() -> usize {
+    let hart_id: usize;
+    unsafe { core::arch::asm!("mv {}, tp", out(reg) hart_id) };
+    hart_id
+}
+
+/// Number of harts taking part in the benchmarks
+pub fn n_harts() -> usize {
+    machine::get().n_harts.min(machine::MAX_HARTS)
+}
+
+/// Number of harts released by kinit, only hart 0 runs in the single hart modes
+pub fn running_harts() -> usize {
+    if assembly::SINGLE_HART {
+        1
+    } else {
+        n_harts()
+    }
+}
+
 use core::time::Duration;
 pub fn time() -> Duration {
-    let mtime = 0x200_BFF8 as *const u64;
-    Duration::from_nanos(unsafe { mtime.read_volatile() } * 100)
+    let ticks = clint::mtime();
+    let frequency = machine::get().timebase_frequency;
+    Duration::from_secs(ticks / frequency)
+        + Duration::from_nanos((ticks % frequency) * 1_000_000_000 / frequency)
 }
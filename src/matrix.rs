@@ -1,4 +1,4 @@
-type Number = i32;
+pub type Number = i32;
 
 #[derive(Debug)]
 pub struct Matrix<
@@ -42,6 +42,17 @@ where
         }
     }
 
+    /// Matrix whose elements are their row-major index: 0, 1, 2, ...
+    pub const fn from_indices() -> Self {
+        let mut data = [0; SIZE];
+        let mut i = 0;
+        while i < SIZE {
+            data[i] = i as Number;
+            i += 1;
+        }
+        Matrix::from_slice(data)
+    }
+
     pub fn sections_mut(
         &mut self,
     ) -> [Option<MatrixSection<'_, SECTION_SIZE, SIDE, SIZE, N_SECTIONS>>; N_SECTIONS] {
@@ -62,6 +73,25 @@ where
     }
 }
 
+impl<
+        'a,
+        const SIDE: usize,
+        const SIZE: usize,
+        const SECTION_SIZE: usize,
+        const N_SECTIONS: usize,
+    > Matrix<'a, SIDE, SIZE, SECTION_SIZE, N_SECTIONS>
+{
+    /// Elements in row-major order
+    pub fn data(&self) -> &[Number] {
+        &self.data
+    }
+
+    /// Sets every element to 0, the kernels accumulate into their result
+    pub fn clear(&mut self) {
+        self.data.fill(0);
+    }
+}
+
 #[derive(Debug)]
 pub struct MatrixSection<
     'a,
@@ -98,6 +128,17 @@ impl<
         }
     }
 
+    /// Sets every element of the section to 0, the kernels accumulate into it
+    pub fn clear(&mut self) {
+        self.section_data.fill(0);
+    }
+
+    /// Addresses of the elements of the section, the only memory its kernels write to
+    pub fn data_range(&self) -> core::ops::Range<usize> {
+        let range = self.section_data.as_ptr_range();
+        range.start as usize..range.end as usize
+    }
+
     pub fn multiply(
         &mut self,
         a: &Matrix<MATRIX_SIDE, MATRIX_SIZE, MATRIX_SIZE, 1>,
@@ -107,8 +148,10 @@ impl<
             .iter_mut()
             .enumerate()
             .for_each(|(i, elem)| {
-                let row = (self.section_number * self.rows + i) / self.cols;
-                let col = i % self.cols;
+                // index of the element in the whole matrix
+                let idx = self.section_number * SIZE + i;
+                let row = idx / self.cols;
+                let col = idx % self.cols;
                 for k in 0..self.cols {
                     *elem += a.data[row * a.cols + k] * b.data[k * b.cols + col];
                 }
@@ -138,8 +181,10 @@ impl<
             .iter_mut()
             .enumerate()
             .for_each(|(i, elem)| {
-                let row = (self.section_number * self.rows + i) / self.cols;
-                let col = i % self.cols;
+                // index of the element in the whole matrix
+                let idx = self.section_number * SECTION_SIZE + i;
+                let row = idx / self.cols;
+                let col = idx % self.cols;
                 for k in 0..kernel.rows {
                     for l in 0..kernel.cols {
                         let y = (row + k) as isize - kernel_y_radius as isize;
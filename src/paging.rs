@@ -0,0 +1,105 @@
+// paging.rs
+// Sv39 identity mapping, for the `paging` feature (supervisor mode, see sbi.rs)
+// The RAM is mapped with pages of `params::PAGE_SIZE` (4 KiB, 2 MiB or 1 GiB) so that the
+// benchmarks pay for the address translation, and the TLB reach, of the chosen page size;
+// the devices below the RAM are mapped with 1 GiB pages whatever the choice
+// Hart 0 builds the tables on the heap once the machine is known, then every hart enables
+// them for itself with `enable`
+
+use crate::{machine, params};
+use alloc::alloc::{alloc_zeroed, Layout};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const ENTRIES: usize = 512;
+const PAGE_SHIFT: usize = 12;
+/// Bits of virtual page number per level
+const LEVEL_BITS: usize = 9;
+const LEVELS: usize = 3;
+const GIGAPAGE: usize = 1 << 30;
+
+const SATP_MODE_SV39: usize = 8 << 60;
+
+// Page table entry bits
+const PTE_V: u64 = 1 << 0;
+const PTE_R: u64 = 1 << 1;
+const PTE_W: u64 = 1 << 2;
+const PTE_X: u64 = 1 << 3;
+const PTE_G: u64 = 1 << 5;
+/// Accessed and dirty are set up front, so no hart ever faults to have them updated
+const PTE_A: u64 = 1 << 6;
+const PTE_D: u64 = 1 << 7;
+const PPN_SHIFT: usize = 10;
+
+const RAM_FLAGS: u64 = PTE_V | PTE_R | PTE_W | PTE_X | PTE_G | PTE_A | PTE_D;
+const DEVICE_FLAGS: u64 = PTE_V | PTE_R | PTE_W | PTE_G | PTE_A | PTE_D;
+
+#[repr(C, align(4096))]
+struct Table([u64; ENTRIES]);
+
+/// Physical address of the root table, 0 until `init` has run
+static ROOT: AtomicUsize = AtomicUsize::new(0);
+static TABLES: AtomicUsize = AtomicUsize::new(0);
+
+/// Level of the leaf entries for pages of `page_size` bytes, 0 for 4 KiB pages
+const fn leaf_level(page_size: usize) -> usize {
+    (page_size.trailing_zeros() as usize - PAGE_SHIFT) / LEVEL_BITS
+}
+
+fn new_table() -> *mut Table {
+    let table = unsafe { alloc_zeroed(Layout::new::<Table>()) } as *mut Table;
+    assert!(!table.is_null(), "out of memory for the page tables");
+    TABLES.fetch_add(1, Ordering::Relaxed);
+    table
+}
+
+fn vpn(address: usize, level: usize) -> usize {
+    (address >> (PAGE_SHIFT + LEVEL_BITS * level)) & (ENTRIES - 1)
+}
+
+/// Maps the page at `address` onto itself, with a leaf entry at `level`
+unsafe fn map(root: *mut Table, address: usize, level: usize, flags: u64) {
+    let mut table = root;
+    for current in (level + 1..LEVELS).rev() {
+        let entry = &mut (*table).0[vpn(address, current)];
+        if *entry & PTE_V == 0 {
+            *entry = ((new_table() as u64) >> PAGE_SHIFT) << PPN_SHIFT | PTE_V;
+        }
+        table = ((*entry >> PPN_SHIFT) << PAGE_SHIFT) as *mut Table;
+    }
+    (*table).0[vpn(address, level)] = ((address as u64) >> PAGE_SHIFT) << PPN_SHIFT | flags;
+}
+
+/// Builds the tables, returns the number of tables
+/// # Safety
+/// Must be called once, by hart 0, after `heap::init`
+pub unsafe fn init() -> usize {
+    let machine = machine::get();
+    let root = new_table();
+
+    // MMIO, everything below the RAM
+    let ram_start = machine.memory_start & !(GIGAPAGE - 1);
+    for address in (0..ram_start).step_by(GIGAPAGE) {
+        map(root, address, LEVELS - 1, DEVICE_FLAGS);
+    }
+
+    let page_size = params::PAGE_SIZE;
+    let start = machine.memory_start & !(page_size - 1);
+    let end = (machine.memory_start + machine.memory_size).next_multiple_of(page_size);
+    for address in (start..end).step_by(page_size) {
+        map(root, address, leaf_level(page_size), RAM_FLAGS);
+    }
+
+    ROOT.store(root as usize, Ordering::Release);
+    TABLES.load(Ordering::Relaxed)
+}
+
+/// Turns on the translation on the calling hart
+/// # Safety
+/// `init` must have completed, the code and data in use must be mapped (which the identity
+/// mapping of the RAM ensures)
+pub unsafe fn enable() {
+    let root = ROOT.load(Ordering::Acquire);
+    assert!(root != 0, "page tables not built");
+    let satp = SATP_MODE_SV39 | root >> PAGE_SHIFT;
+    core::arch::asm!("csrw satp, {}", "sfence.vma", in(reg) satp);
+}
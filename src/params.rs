@@ -0,0 +1,56 @@
+// params.rs
+// Build-time parameters, read from the environment of the cargo invocation
+// e.g. `BENCH_SIDE=16 BENCH_ITERATIONS=10 cargo run --features parallel`
+
+/// Side of the matrices of the fixed-size benchmarks
+pub const SIDE: usize = parse_or(option_env!("BENCH_SIDE"), 4);
+
+/// Timed repetitions of the computation in the fixed-size benchmarks, `BENCH_ITERATIONS` must
+/// be at least 1
+pub const ITERATIONS: usize = parse_or(option_env!("BENCH_ITERATIONS"), 5);
+const _: () = assert!(ITERATIONS >= 1, "BENCH_ITERATIONS must be at least 1");
+
+/// Stack of each hart in bytes, `BENCH_STACK_SIZE` must be a multiple of 16 of at least 4096
+/// build.rs reads the same variable to size the stack region of the linker script
+pub const STACK_SIZE: usize = parse_or(option_env!("BENCH_STACK_SIZE"), DEFAULT_STACK_SIZE);
+pub const DEFAULT_STACK_SIZE: usize = 64 * 1024;
+const _: () = assert!(
+    STACK_SIZE.is_multiple_of(16) && STACK_SIZE >= 4096,
+    "BENCH_STACK_SIZE must be a multiple of 16 of at least 4096"
+);
+
+/// Size of the pages mapping the RAM with the `paging` feature, `BENCH_PAGE_SIZE` is one of
+/// 4K (the default), 2M or 1G
+pub const PAGE_SIZE: usize = parse_page_size(option_env!("BENCH_PAGE_SIZE"));
+
+const fn parse_page_size(value: Option<&str>) -> usize {
+    match value {
+        None => 4 << 10,
+        Some(value) => match value.as_bytes() {
+            b"4K" | b"4k" => 4 << 10,
+            b"2M" | b"2m" => 2 << 20,
+            b"1G" | b"1g" => 1 << 30,
+            _ => panic!("BENCH_PAGE_SIZE must be 4K, 2M or 1G"),
+        },
+    }
+}
+
+/// Parses a decimal number at compile time, `default` if the variable is not set
+const fn parse_or(value: Option<&str>, default: usize) -> usize {
+    let Some(value) = value else {
+        return default;
+    };
+    let digits = value.as_bytes();
+    assert!(!digits.is_empty(), "empty numeric build parameter");
+    let mut result = 0;
+    let mut i = 0;
+    while i < digits.len() {
+        assert!(
+            digits[i].is_ascii_digit(),
+            "numeric build parameter is not a decimal number"
+        );
+        result = result * 10 + (digits[i] - b'0') as usize;
+        i += 1;
+    }
+    result
+}
@@ -0,0 +1,51 @@
+// platform.rs
+// Board specific constants, selected with a cargo feature (QEMU virt when none is given)
+// Each board also has its own linker script, chosen by build.rs
+// Everything that can be discovered from the device tree is (see machine.rs), the platform
+// only provides what cannot: the fallback configuration and the hart layout
+//
+// Harts below `FIRST_HART` are monitor cores that cannot run the benchmarks and stay parked
+// in boot.s. All the other code uses logical hart ids, counted from the first application
+// hart, which the CLINT and PLIC drivers translate back with `physical_hart`
+
+use crate::machine::Machine;
+use crate::privilege;
+
+#[cfg(all(feature = "virt", feature = "sifive_u"))]
+compile_error!("the `virt` and `sifive_u` features select different platforms, enable only one");
+
+#[cfg(all(feature = "sifive_u", not(feature = "virt")))]
+mod sifive_u;
+#[cfg(any(feature = "virt", not(feature = "sifive_u")))]
+mod virt;
+
+pub trait Platform {
+    const NAME: &'static str;
+    /// Configuration used when the firmware does not pass a valid device tree
+    const DEFAULT_MACHINE: Machine;
+    /// mhartid of the first hart that runs the benchmarks, which also boots the others
+    const FIRST_HART: usize;
+
+    /// PLIC context of machine mode on the hart with id `mhartid`
+    /// The supervisor mode context, if the hart has one, is the next one
+    fn plic_context(mhartid: usize) -> usize;
+}
+
+#[cfg(all(feature = "sifive_u", not(feature = "virt")))]
+pub type Current = sifive_u::SifiveU;
+#[cfg(any(feature = "virt", not(feature = "sifive_u")))]
+pub type Current = virt::Virt;
+
+pub const NAME: &str = Current::NAME;
+pub const DEFAULT_MACHINE: Machine = Current::DEFAULT_MACHINE;
+pub const FIRST_HART: usize = Current::FIRST_HART;
+
+/// mhartid of the logical hart `hart_id`
+pub fn physical_hart(hart_id: usize) -> usize {
+    hart_id + FIRST_HART
+}
+
+/// PLIC context of the privilege level the firmware runs in on `hart_id`
+pub fn plic_context(hart_id: usize) -> usize {
+    Current::plic_context(physical_hart(hart_id)) + privilege::SUPERVISOR as usize
+}
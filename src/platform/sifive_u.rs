@@ -0,0 +1,43 @@
+// sifive_u.rs
+// QEMU sifive_u machine, modelled on the SiFive FU540 (HiFive Unleashed)
+// Hart 0 is the E51 monitor core (rv64imac, no FPU and no supervisor mode), which cannot run
+// this firmware, harts 1 to 4 are the U54 application cores, e.g.
+// qemu-system-riscv64 -machine sifive_u -smp 5 -m 128M -nographic -bios none -kernel <elf>
+
+use super::Platform;
+use crate::machine::Machine;
+use crate::serial;
+
+pub struct SifiveU;
+
+impl Platform for SifiveU {
+    const NAME: &'static str = "QEMU sifive_u";
+
+    const DEFAULT_MACHINE: Machine = Machine {
+        n_harts: 4,
+        memory_start: 0x8000_0000,
+        memory_size: 128 * 1024 * 1024,
+        // UART0, clocked by tlclk
+        uart_base: 0x1001_0000,
+        uart_kind: serial::Kind::Sifive,
+        uart_clock: 500_000_000,
+        uart_irq: 4,
+        clint_base: 0x200_0000,
+        plic_base: 0xc00_0000,
+        test_base: Some(0x10_0000),
+        timebase_frequency: 1_000_000,
+        from_device_tree: false,
+        dtb: None,
+    };
+
+    const FIRST_HART: usize = 1;
+
+    /// The E51 only has a machine context, the U54s have a machine and a supervisor one
+    fn plic_context(mhartid: usize) -> usize {
+        if mhartid == 0 {
+            0
+        } else {
+            2 * mhartid - 1
+        }
+    }
+}
@@ -0,0 +1,36 @@
+// virt.rs
+// QEMU virt machine, as started by the runner in .cargo/config.toml
+
+use super::Platform;
+use crate::machine::Machine;
+use crate::serial;
+
+pub struct Virt;
+
+impl Platform for Virt {
+    const NAME: &'static str = "QEMU virt";
+
+    const DEFAULT_MACHINE: Machine = Machine {
+        n_harts: 4,
+        memory_start: 0x8000_0000,
+        memory_size: 128 * 1024 * 1024,
+        uart_base: 0x1000_0000,
+        uart_kind: serial::Kind::Ns16550,
+        uart_clock: 3_686_400,
+        uart_irq: 10,
+        clint_base: 0x200_0000,
+        plic_base: 0xc00_0000,
+        test_base: Some(0x10_0000),
+        timebase_frequency: 10_000_000,
+        from_device_tree: false,
+        dtb: None,
+    };
+
+    /// Every hart is an application core
+    const FIRST_HART: usize = 0;
+
+    /// Every hart has a machine and a supervisor context, in this order
+    fn plic_context(mhartid: usize) -> usize {
+        2 * mhartid
+    }
+}
@@ -0,0 +1,114 @@
+// plic.rs
+// Platform-Level Interrupt Controller driver
+// Each hart takes the external interrupts of its machine mode context (supervisor mode with
+// `sbi`), whose number depends on the platform (on the QEMU virt machine it is 2 * hart_id,
+// the odd contexts belong to supervisor mode)
+
+use crate::{machine, platform};
+use crate::{print, println};
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+const PRIORITY_OFFSET: usize = 0x0;
+const ENABLE_OFFSET: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT_OFFSET: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const THRESHOLD: usize = 0x0;
+const CLAIM_COMPLETE: usize = 0x4;
+
+/// Interrupt sources that can have a handler
+pub const MAX_IRQS: usize = 128;
+
+/// Called with the interrupt source that was claimed
+pub type IrqHandler = fn(irq: u32);
+
+static HANDLERS: [AtomicPtr<()>; MAX_IRQS] =
+    [const { AtomicPtr::new(core::ptr::null_mut()) }; MAX_IRQS];
+
+fn base() -> usize {
+    machine::get().plic_base
+}
+
+fn context(hart_id: usize) -> usize {
+    platform::plic_context(hart_id)
+}
+
+fn context_register(hart_id: usize, offset: usize) -> *mut u32 {
+    (base() + CONTEXT_OFFSET + CONTEXT_STRIDE * context(hart_id) + offset) as *mut u32
+}
+
+/// Sets the priority of `irq`, 0 means never interrupt
+pub fn set_priority(irq: u32, priority: u32) {
+    let register = (base() + PRIORITY_OFFSET + 4 * irq as usize) as *mut u32;
+    unsafe { register.write_volatile(priority) };
+}
+
+fn enable_register(hart_id: usize, irq: u32) -> (*mut u32, u32) {
+    let register =
+        base() + ENABLE_OFFSET + ENABLE_STRIDE * context(hart_id) + 4 * (irq as usize / 32);
+    (register as *mut u32, 1 << (irq % 32))
+}
+
+/// Lets `irq` interrupt `hart_id`
+pub fn enable(hart_id: usize, irq: u32) {
+    let (register, bit) = enable_register(hart_id, irq);
+    unsafe { register.write_volatile(register.read_volatile() | bit) };
+}
+
+pub fn disable(hart_id: usize, irq: u32) {
+    let (register, bit) = enable_register(hart_id, irq);
+    unsafe { register.write_volatile(register.read_volatile() & !bit) };
+}
+
+/// Only interrupts with a priority above `threshold` are delivered to `hart_id`
+pub fn set_threshold(hart_id: usize, threshold: u32) {
+    unsafe { context_register(hart_id, THRESHOLD).write_volatile(threshold) };
+}
+
+/// Claims the highest priority pending interrupt of `hart_id`
+pub fn claim(hart_id: usize) -> Option<u32> {
+    match unsafe { context_register(hart_id, CLAIM_COMPLETE).read_volatile() } {
+        0 => None,
+        irq => Some(irq),
+    }
+}
+
+/// Signals that `irq` has been serviced, so that it can be delivered again
+pub fn complete(hart_id: usize, irq: u32) {
+    unsafe { context_register(hart_id, CLAIM_COMPLETE).write_volatile(irq) };
+}
+
+/// Interrupt source that cannot have a handler, it is not below `MAX_IRQS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidIrq(pub u32);
+
+/// Registers the handler of `irq`, replacing the previous one
+/// `irq` usually comes from the device tree, so it is checked like when dispatching
+pub fn register_handler(irq: u32, handler: IrqHandler) -> Result<(), InvalidIrq> {
+    HANDLERS
+        .get(irq as usize)
+        .ok_or(InvalidIrq(irq))?
+        .store(handler as *mut (), Ordering::SeqCst);
+    Ok(())
+}
+
+/// Machine external interrupt handler, see `trap::register_handler`
+/// Claims and dispatches every pending interrupt of the hart
+pub fn handle_interrupt(hart_id: usize) {
+    while let Some(irq) = claim(hart_id) {
+        let handler = HANDLERS
+            .get(irq as usize)
+            .map_or(core::ptr::null_mut(), |handler| {
+                handler.load(Ordering::SeqCst)
+            });
+        if handler.is_null() {
+            // nobody is interested in it, do not let it fire again
+            disable(hart_id, irq);
+            println!("Unhandled external interrupt {} on hart {}", irq, hart_id);
+        } else {
+            let handler = unsafe { core::mem::transmute::<*mut (), IrqHandler>(handler) };
+            handler(irq);
+        }
+        complete(hart_id, irq);
+    }
+}
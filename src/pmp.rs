@@ -0,0 +1,79 @@
+// pmp.rs
+// Physical memory protection for the code running in U-mode (see user.rs)
+// With `user_mode` U-mode may access all the memory (`allow_all`); with `pmp` the section
+// kernels are isolated instead: `run_isolated` only allows writes to the section of the hart and
+// to its user stack, everything else being readable and executable. A write anywhere else is a
+// store access fault, reported with the hart and the address by `report_store_fault`
+
+#[cfg(feature = "pmp")]
+use crate::user;
+#[cfg(feature = "pmp")]
+use core::ops::Range;
+
+// pmpcfg fields
+const PMP_R: usize = 1 << 0;
+const PMP_W: usize = 1 << 1;
+const PMP_X: usize = 1 << 2;
+/// Top of range: the entry covers [pmpaddr of the previous entry, its own pmpaddr)
+#[cfg(feature = "pmp")]
+const PMP_TOR: usize = 1 << 3;
+/// Naturally aligned power of two, an all ones pmpaddr covers the whole address space
+const PMP_NAPOT: usize = 3 << 3;
+/// pmpaddr holds bits 2 and up of the address
+#[cfg(feature = "pmp")]
+const PMP_SHIFT: usize = 2;
+
+/// Gives U-mode access to all the memory, through entry 0
+/// M-mode is never restricted since no entry is locked
+/// # Safety
+/// Replaces the PMP configuration of the calling hart
+pub unsafe fn allow_all() {
+    core::arch::asm!(
+        "csrw pmpaddr0, {}",
+        "csrw pmpcfg0, {}",
+        in(reg) usize::MAX,
+        in(reg) PMP_NAPOT | PMP_R | PMP_W | PMP_X,
+    );
+}
+
+/// Runs `f` in U-mode on the calling hart, only allowing it to write to `writable`
+/// and to its own stack
+#[cfg(feature = "pmp")]
+pub fn run_isolated<F: FnOnce()>(writable: Range<usize>, f: F) {
+    unsafe {
+        configure(&writable, &user::stack(crate::hart_id()));
+        user::enter(f);
+    }
+}
+
+/// Programs the PMP of the calling hart: entries 1 and 3 allow reads and writes to `writable`
+/// and `stack` (entries 0 and 2 only hold their start), entry 4 allows reads and execution
+/// anywhere
+#[cfg(feature = "pmp")]
+unsafe fn configure(writable: &Range<usize>, stack: &Range<usize>) {
+    let rw = PMP_TOR | PMP_R | PMP_W;
+    let cfg = rw << 8 | rw << 24 | (PMP_NAPOT | PMP_R | PMP_X) << 32;
+    core::arch::asm!(
+        "csrw pmpaddr0, {}",
+        "csrw pmpaddr1, {}",
+        "csrw pmpaddr2, {}",
+        "csrw pmpaddr3, {}",
+        "csrw pmpaddr4, {}",
+        "csrw pmpcfg0, {}",
+        in(reg) writable.start >> PMP_SHIFT,
+        in(reg) writable.end >> PMP_SHIFT,
+        in(reg) stack.start >> PMP_SHIFT,
+        in(reg) stack.end >> PMP_SHIFT,
+        in(reg) usize::MAX,
+        in(reg) cfg,
+    );
+}
+
+/// Reports a write of an isolated kernel outside of what `run_isolated` allows
+#[cfg(feature = "pmp")]
+pub fn report_store_fault(hart_id: usize, address: usize, epc: usize) -> ! {
+    panic!(
+        "Hart {} wrote to {:#x}, outside its section (pc {:#x})",
+        hart_id, address, epc
+    )
+}
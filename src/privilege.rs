@@ -0,0 +1,42 @@
+// privilege.rs
+// Control and status registers of the privilege level the firmware runs in: machine mode,
+// or supervisor mode as an OpenSBI payload with the `sbi` feature (see sbi.rs)
+// The supervisor CSRs mirror the machine ones 0x200 lower, and the supervisor interrupt
+// codes are the machine ones minus 2
+
+/// true if the firmware runs in supervisor mode
+pub const SUPERVISOR: bool = cfg!(feature = "sbi");
+
+const fn csr(machine: usize) -> usize {
+    if SUPERVISOR {
+        machine - 0x200
+    } else {
+        machine
+    }
+}
+
+const fn interrupt(machine: usize) -> usize {
+    if SUPERVISOR {
+        machine - 2
+    } else {
+        machine
+    }
+}
+
+/// mstatus or sstatus
+pub const STATUS: usize = csr(0x300);
+pub const IE: usize = csr(0x304);
+pub const TVEC: usize = csr(0x305);
+pub const SCRATCH: usize = csr(0x340);
+pub const EPC: usize = csr(0x341);
+pub const CAUSE: usize = csr(0x342);
+pub const TVAL: usize = csr(0x343);
+pub const IP: usize = csr(0x344);
+
+/// Global interrupt enable bit of the status register (MIE or SIE)
+pub const STATUS_IE: usize = if SUPERVISOR { 1 << 1 } else { 1 << 3 };
+
+/// Interrupt codes of the cause register, also the bits in the ie/ip registers
+pub const SOFTWARE_INTERRUPT: usize = interrupt(3);
+pub const TIMER_INTERRUPT: usize = interrupt(7);
+pub const EXTERNAL_INTERRUPT: usize = interrupt(11);
@@ -0,0 +1,226 @@
+// report.rs
+// Machine-readable record of a benchmark run, printed on its own line after the
+// human-readable output
+// The format is selected with the `report_json` (one JSON object per line) or `report_csv`
+// (a header line, then one record per run) feature, without either nothing is printed
+
+use crate::matrix::Number;
+use core::time::Duration;
+
+/// Scheduling mode of the image, as selected by the cargo features
+pub const MODE: &str = if cfg!(feature = "sequential") {
+    "sequential"
+} else if cfg!(feature = "dynamic") {
+    "dynamic"
+} else if cfg!(feature = "sweep") {
+    "sweep"
+} else if cfg!(feature = "shell") {
+    "shell"
+} else {
+    "parallel"
+};
+
+/// Size of the pages the RAM is mapped with, see paging.rs, "off" without translation
+pub const PAGING: &str = if !cfg!(feature = "paging") {
+    "off"
+} else {
+    match crate::params::PAGE_SIZE {
+        0x1000 => "4K",
+        0x20_0000 => "2M",
+        _ => "1G",
+    }
+};
+
+/// Memory the benchmark matrices are placed in, see the scratchpad region of the linker script
+pub const PLACEMENT: &str = match (
+    cfg!(feature = "scratchpad_inputs"),
+    cfg!(feature = "scratchpad_output"),
+) {
+    (false, false) => "ram",
+    (true, false) => "scratchpad_inputs",
+    (false, true) => "scratchpad_output",
+    (true, true) => "scratchpad",
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    Passed,
+    Failed,
+    /// The result was not checked
+    Skipped,
+}
+
+impl Verification {
+    pub fn from_check(passed: bool) -> Self {
+        if passed {
+            Verification::Passed
+        } else {
+            Verification::Failed
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Verification::Passed => "passed",
+            Verification::Failed => "failed",
+            Verification::Skipped => "skipped",
+        }
+    }
+}
+
+/// Statistics over the iterations of a run
+#[derive(Debug, Clone, Copy)]
+pub struct Timing {
+    pub iterations: u32,
+    pub min: Duration,
+    pub max: Duration,
+    pub total: Duration,
+}
+
+impl Timing {
+    pub const fn new() -> Self {
+        Timing {
+            iterations: 0,
+            min: Duration::MAX,
+            max: Duration::ZERO,
+            total: Duration::ZERO,
+        }
+    }
+
+    pub fn record(&mut self, elapsed: Duration) {
+        self.iterations += 1;
+        self.min = self.min.min(elapsed);
+        self.max = self.max.max(elapsed);
+        self.total += elapsed;
+    }
+
+    pub fn avg(&self) -> Duration {
+        self.total.checked_div(self.iterations).unwrap_or_default()
+    }
+}
+
+/// "min .., avg .., max .." over the iterations, or the single sample
+impl core::fmt::Display for Timing {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.iterations == 1 {
+            write!(f, "{:?} (1 iteration)", self.min)
+        } else {
+            write!(
+                f,
+                "min {:?}, avg {:?}, max {:?} over {} iterations",
+                self.min,
+                self.avg(),
+                self.max,
+                self.iterations
+            )
+        }
+    }
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Run<'a> {
+    pub benchmark: &'a str,
+    /// Side of the result matrix
+    pub size: usize,
+    pub harts: usize,
+    pub mode: &'a str,
+    pub timing: Timing,
+    pub verification: Verification,
+}
+
+/// Name of the type of the matrix elements
+pub fn element_type() -> &'static str {
+    core::any::type_name::<Number>()
+}
+
+/// Prints the record of `run` in the selected format
+pub fn emit(run: &Run) {
+    #[cfg(feature = "report_json")]
+    json::emit(run);
+    #[cfg(feature = "report_csv")]
+    csv::emit(run);
+    #[cfg(not(any(feature = "report_json", feature = "report_csv")))]
+    let _ = run;
+}
+
+#[cfg(feature = "report_json")]
+mod json {
+    use super::Run;
+    use crate::{print, println};
+    use core::fmt::{Display, Write};
+
+    /// Writes a string as a JSON string literal
+    struct JsonStr<'a>(&'a str);
+
+    impl Display for JsonStr<'_> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            f.write_char('"')?;
+            for c in self.0.chars() {
+                match c {
+                    '"' => f.write_str("\\\"")?,
+                    '\\' => f.write_str("\\\\")?,
+                    c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                    c => f.write_char(c)?,
+                }
+            }
+            f.write_char('"')
+        }
+    }
+
+    pub fn emit(run: &Run) {
+        println!(
+            "{{\"benchmark\":{},\"element_type\":{},\"size\":{},\"harts\":{},\"mode\":{},\"paging\":{},\"placement\":{},\"iterations\":{},\"min_ns\":{},\"avg_ns\":{},\"max_ns\":{},\"verification\":{}}}",
+            JsonStr(run.benchmark),
+            JsonStr(super::element_type()),
+            run.size,
+            run.harts,
+            JsonStr(run.mode),
+            JsonStr(super::PAGING),
+            JsonStr(super::PLACEMENT),
+            run.timing.iterations,
+            run.timing.min.as_nanos(),
+            run.timing.avg().as_nanos(),
+            run.timing.max.as_nanos(),
+            JsonStr(run.verification.as_str())
+        );
+    }
+}
+
+#[cfg(feature = "report_csv")]
+mod csv {
+    use super::Run;
+    use crate::{print, println};
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    pub const HEADER: &str =
+        "benchmark,element_type,size,harts,mode,paging,placement,iterations,min_ns,avg_ns,max_ns,verification";
+
+    static HEADER_PRINTED: AtomicBool = AtomicBool::new(false);
+
+    /// The fields are identifiers and numbers, so they never need quoting
+    pub fn emit(run: &Run) {
+        if !HEADER_PRINTED.swap(true, Ordering::SeqCst) {
+            println!("{}", HEADER);
+        }
+        println!(
+            "{},{},{},{},{},{},{},{},{},{},{},{}",
+            run.benchmark,
+            super::element_type(),
+            run.size,
+            run.harts,
+            run.mode,
+            super::PAGING,
+            super::PLACEMENT,
+            run.timing.iterations,
+            run.timing.min.as_nanos(),
+            run.timing.avg().as_nanos(),
+            run.timing.max.as_nanos(),
+            run.verification.as_str()
+        );
+    }
+}
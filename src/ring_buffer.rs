@@ -0,0 +1,60 @@
+// ring_buffer.rs
+// Lock-free single producer, single consumer byte queue
+// e.g. filled by an interrupt handler on one hart and drained by a reader on another
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct RingBuffer<const N: usize> {
+    data: UnsafeCell<[u8; N]>,
+    /// Number of bytes ever popped, only written by the consumer
+    head: AtomicUsize,
+    /// Number of bytes ever pushed, only written by the producer
+    tail: AtomicUsize,
+}
+
+unsafe impl<const N: usize> Sync for RingBuffer<N> {}
+
+impl<const N: usize> RingBuffer<N> {
+    pub const fn new() -> Self {
+        RingBuffer {
+            data: UnsafeCell::new([0; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Producer side, returns false (dropping `value`) if the buffer is full
+    pub fn push(&self, value: u8) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) == N {
+            return false;
+        }
+        unsafe { (*self.data.get())[tail % N] = value };
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    /// Consumer side
+    pub fn pop(&self) -> Option<u8> {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        let value = unsafe { (*self.data.get())[head % N] };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+    }
+}
+
+impl<const N: usize> Default for RingBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,168 @@
+// sbi.rs
+// Supervisor Binary Interface calls, for the `sbi` feature
+// The firmware then runs in supervisor mode as the payload of OpenSBI, which owns machine
+// mode: harts are started with the HSM extension, the timer and the IPIs go through the
+// TIME and IPI extensions (see clint_sbi.rs) and the console through the debug console
+// The cargo runner starts QEMU without a firmware (-bios none), run the image with e.g.
+// qemu-system-riscv64 -machine virt -smp 4 -m 128M -nographic -bios default -kernel <elf>
+
+use crate::platform;
+use crate::serial::Serial;
+use core::fmt::Write;
+
+pub const EXT_BASE: usize = 0x10;
+pub const EXT_TIME: usize = 0x5449_4d45;
+pub const EXT_IPI: usize = 0x73_5049;
+pub const EXT_HSM: usize = 0x48_534d;
+pub const EXT_SRST: usize = 0x5352_5354;
+pub const EXT_DBCN: usize = 0x4442_434e;
+
+const BASE_PROBE_EXTENSION: usize = 3;
+const TIME_SET_TIMER: usize = 0;
+const IPI_SEND_IPI: usize = 0;
+const HSM_HART_START: usize = 0;
+const SRST_SYSTEM_RESET: usize = 0;
+const DBCN_CONSOLE_WRITE: usize = 0;
+const DBCN_CONSOLE_READ: usize = 1;
+const DBCN_CONSOLE_WRITE_BYTE: usize = 2;
+
+/// SRST reset types
+pub const RESET_SHUTDOWN: usize = 0;
+pub const RESET_COLD_REBOOT: usize = 1;
+/// SRST reset reasons
+pub const REASON_NONE: usize = 0;
+pub const REASON_SYSTEM_FAILURE: usize = 1;
+
+/// Error code returned by the SBI implementation, e.g. -2 for an unsupported function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error(pub isize);
+
+fn call(extension: usize, function: usize, args: [usize; 3]) -> Result<usize, Error> {
+    let error: isize;
+    let value: usize;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") args[0] => error,
+            inlateout("a1") args[1] => value,
+            in("a2") args[2],
+            in("a6") function,
+            in("a7") extension,
+        );
+    }
+    if error == 0 {
+        Ok(value)
+    } else {
+        Err(Error(error))
+    }
+}
+
+pub fn probe_extension(extension: usize) -> bool {
+    call(EXT_BASE, BASE_PROBE_EXTENSION, [extension, 0, 0]).is_ok_and(|value| value != 0)
+}
+
+/// Programs the timer of the calling hart, which also clears its pending timer interrupt
+pub fn set_timer(value: u64) {
+    let _ = call(EXT_TIME, TIME_SET_TIMER, [value as usize, 0, 0]);
+}
+
+/// Raises a software interrupt on the harts of `hart_mask`, bit i standing for the hart
+/// with mhartid `hart_mask_base + i`
+pub fn send_ipi(hart_mask: usize, hart_mask_base: usize) -> Result<(), Error> {
+    call(EXT_IPI, IPI_SEND_IPI, [hart_mask, hart_mask_base, 0]).map(|_| ())
+}
+
+/// Starts the logical hart `hart_id` at `_start_hart` in boot_sbi.s
+pub fn start_hart(hart_id: usize) -> Result<(), Error> {
+    extern "C" {
+        fn _start_hart();
+    }
+    let start = _start_hart as *const () as usize;
+    call(
+        EXT_HSM,
+        HSM_HART_START,
+        [platform::physical_hart(hart_id), start, 0],
+    )
+    .map(|_| ())
+}
+
+/// Resets the system, only returns if the reset was refused
+pub fn system_reset(reset_type: usize, reason: usize) -> Error {
+    match call(EXT_SRST, SRST_SYSTEM_RESET, [reset_type, reason, 0]) {
+        Ok(_) => Error(0),
+        Err(error) => error,
+    }
+}
+
+/// Console of the SBI implementation (DBCN extension), which drives the UART itself
+/// Input can only be polled
+pub struct DebugConsole {
+    baud_rate: u32,
+}
+
+impl DebugConsole {
+    /// None if the SBI implementation does not provide the debug console (before SBI 2.0)
+    pub fn new(baud_rate: u32) -> Option<Self> {
+        probe_extension(EXT_DBCN).then_some(DebugConsole { baud_rate })
+    }
+}
+
+impl Write for DebugConsole {
+    fn write_str(&mut self, out: &str) -> Result<(), core::fmt::Error> {
+        // the buffer is passed by physical address, which is its address as the RAM is
+        // either not translated or identity mapped (see paging.rs)
+        let mut bytes = out.as_bytes();
+        while !bytes.is_empty() {
+            let written = call(
+                EXT_DBCN,
+                DBCN_CONSOLE_WRITE,
+                [bytes.len(), bytes.as_ptr() as usize, 0],
+            )
+            .map_err(|_| core::fmt::Error)?;
+            bytes = &bytes[written.min(bytes.len())..];
+        }
+        Ok(())
+    }
+}
+
+impl Serial for DebugConsole {
+    fn init(&mut self) {
+        write!(self, "SBI debug console initialized\r\n").unwrap();
+    }
+
+    /// The line is configured by the SBI implementation, the rate is only recorded
+    fn set_baud_rate(&mut self, baud_rate: u32) {
+        self.baud_rate = baud_rate;
+    }
+
+    fn baud_rate(&self) -> u32 {
+        self.baud_rate
+    }
+
+    fn put(&mut self, c: u8) {
+        let _ = call(EXT_DBCN, DBCN_CONSOLE_WRITE_BYTE, [c as usize, 0, 0]);
+    }
+
+    fn get(&mut self) -> Option<u8> {
+        let mut c = 0u8;
+        match call(
+            EXT_DBCN,
+            DBCN_CONSOLE_READ,
+            [1, core::ptr::addr_of_mut!(c) as usize, 0],
+        ) {
+            Ok(1) => Some(c),
+            _ => None,
+        }
+    }
+
+    /// Output is written synchronously by the SBI implementation
+    fn enable_buffered_tx(&mut self) {}
+
+    fn handle_interrupt(&mut self) {}
+
+    fn flush(&mut self) {}
+
+    fn interrupt_driven(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,201 @@
+// serial.rs
+// Interface shared by the UART drivers, so the console works with whichever UART the
+// machine has (see `Kind`, found in the device tree or given by the platform)
+
+use crate::machine::Machine;
+#[cfg(feature = "sbi")]
+use crate::sbi::DebugConsole;
+use crate::sifive_uart::SifiveUart;
+use crate::uart::Uart;
+use core::fmt::{Error, Write};
+
+/// Baud rate used by the console
+pub const DEFAULT_BAUD_RATE: u32 = 115_200;
+
+const TX_BUFFER_SIZE: usize = 1024;
+
+pub trait Serial: Write {
+    /// Sets the line format and the baud rate and enables the receive interrupt
+    fn init(&mut self);
+    fn set_baud_rate(&mut self, baud_rate: u32);
+    fn baud_rate(&self) -> u32;
+    fn put(&mut self, c: u8);
+    /// Returns the next received character, without waiting
+    fn get(&mut self) -> Option<u8>;
+    /// Switches to buffered transmission, the buffer is drained from `handle_interrupt`
+    fn enable_buffered_tx(&mut self);
+    /// Services the transmit side of an interrupt of the UART
+    /// Received characters must be read with `get` first
+    fn handle_interrupt(&mut self);
+    /// Sends everything still waiting in the transmit buffer
+    fn flush(&mut self);
+
+    /// false if input can only be polled, the UART interrupt is then left alone
+    fn interrupt_driven(&self) -> bool {
+        true
+    }
+}
+
+/// UART register layouts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// NS16550A compatible, e.g. on the QEMU virt machine
+    Ns16550,
+    /// SiFive UART, e.g. on the FU540 and the QEMU sifive_u machine
+    Sifive,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 2] = [Kind::Ns16550, Kind::Sifive];
+
+    /// `compatible` string of the device tree node
+    pub fn compatible(&self) -> &'static str {
+        match self {
+            Kind::Ns16550 => "ns16550a",
+            Kind::Sifive => "sifive,uart0",
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Ns16550 => "16550",
+            Kind::Sifive => "SiFive",
+        }
+    }
+}
+
+/// The UART of the machine, whatever its kind
+pub enum Port {
+    Ns16550(Uart),
+    Sifive(SifiveUart),
+    /// Console of the SBI implementation, preferred to the UART when it is available
+    #[cfg(feature = "sbi")]
+    Sbi(DebugConsole),
+}
+
+impl Port {
+    pub fn new(machine: &Machine, baud_rate: u32) -> Self {
+        #[cfg(feature = "sbi")]
+        if let Some(console) = DebugConsole::new(baud_rate) {
+            return Port::Sbi(console);
+        }
+        match machine.uart_kind {
+            Kind::Ns16550 => {
+                Port::Ns16550(Uart::new(machine.uart_base, machine.uart_clock, baud_rate))
+            }
+            Kind::Sifive => Port::Sifive(SifiveUart::new(
+                machine.uart_base,
+                machine.uart_clock,
+                baud_rate,
+            )),
+        }
+    }
+
+    fn serial_ref(&self) -> &dyn Serial {
+        match self {
+            Port::Ns16550(uart) => uart,
+            Port::Sifive(uart) => uart,
+            #[cfg(feature = "sbi")]
+            Port::Sbi(console) => console,
+        }
+    }
+
+    fn serial(&mut self) -> &mut dyn Serial {
+        match self {
+            Port::Ns16550(uart) => uart,
+            Port::Sifive(uart) => uart,
+            #[cfg(feature = "sbi")]
+            Port::Sbi(console) => console,
+        }
+    }
+}
+
+impl Write for Port {
+    fn write_str(&mut self, out: &str) -> Result<(), Error> {
+        self.serial().write_str(out)
+    }
+}
+
+impl Serial for Port {
+    fn init(&mut self) {
+        self.serial().init()
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) {
+        self.serial().set_baud_rate(baud_rate)
+    }
+
+    fn baud_rate(&self) -> u32 {
+        self.serial_ref().baud_rate()
+    }
+
+    fn put(&mut self, c: u8) {
+        self.serial().put(c)
+    }
+
+    fn get(&mut self) -> Option<u8> {
+        self.serial().get()
+    }
+
+    fn enable_buffered_tx(&mut self) {
+        self.serial().enable_buffered_tx()
+    }
+
+    fn handle_interrupt(&mut self) {
+        self.serial().handle_interrupt()
+    }
+
+    fn flush(&mut self) {
+        self.serial().flush()
+    }
+
+    fn interrupt_driven(&self) -> bool {
+        self.serial_ref().interrupt_driven()
+    }
+}
+
+/// Ring buffer of the characters waiting for the transmitter
+pub struct TxBuffer {
+    data: [u8; TX_BUFFER_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl TxBuffer {
+    pub fn new() -> Self {
+        TxBuffer {
+            data: [0; TX_BUFFER_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, c: u8) {
+        self.data[(self.head + self.len) % TX_BUFFER_SIZE] = c;
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let c = self.data[self.head];
+        self.head = (self.head + 1) % TX_BUFFER_SIZE;
+        self.len -= 1;
+        Some(c)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == TX_BUFFER_SIZE
+    }
+}
+
+impl Default for TxBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,10 +1,73 @@
 use crate::matrix::{Matrix, MatrixSection};
-use crate::N_HARTS;
+use crate::spin_mutex::{LockStats, TicketMutex};
+use crate::watchdog::Watchable;
+use crate::{print, println};
 use core::cell::UnsafeCell;
 use core::fmt::Display;
-use core::sync::atomic::{AtomicBool, AtomicUsize};
+use core::sync::atomic::AtomicUsize;
 
-const ATOMIC_TRUE: AtomicBool = AtomicBool::new(true);
+/// Value of `processed_by` for a section that has not been computed yet
+const NOT_PROCESSED: usize = usize::MAX;
+
+/// Misuse of a `SharedMatrix` when claiming or computing sections
+/// A result that is not complete yet is not an error: `try_result` returns None
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SharedMatrixError {
+    /// There are only `sections` sections
+    OutOfRange { index: usize, sections: usize },
+    /// The section was claimed before, by this hart or another one
+    AlreadyClaimed(usize),
+    /// `initialize` has not been called yet
+    NotInitialized,
+}
+
+impl Display for SharedMatrixError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SharedMatrixError::OutOfRange { index, sections } => write!(
+                f,
+                "section {} is out of range, there are {} sections",
+                index, sections
+            ),
+            SharedMatrixError::AlreadyClaimed(index) => {
+                write!(f, "section {} has already been claimed", index)
+            }
+            SharedMatrixError::NotInitialized => write!(f, "the matrix is not initialized"),
+        }
+    }
+}
+
+/// Error of `compute_dynamic`, after the calling hart computed `processed` sections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DynamicError {
+    pub processed: usize,
+    pub error: SharedMatrixError,
+}
+
+impl Display for DynamicError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} after computing {} sections",
+            self.error, self.processed
+        )
+    }
+}
+
+/// Bookkeeping of the sections, behind the lock of the shared matrix
+#[derive(Debug)]
+struct Sections<
+    'a,
+    const SIDE: usize,
+    const SIZE: usize,
+    const SECTION_SIZE: usize,
+    const N_SECTIONS: usize,
+> {
+    /// Sections waiting to be claimed, or given back once computed
+    sections: [Option<MatrixSection<'a, SECTION_SIZE, SIDE, SIZE, N_SECTIONS>>; N_SECTIONS],
+    /// Sections handed out by `claim_section`, they stay claimed once computed until `reset`
+    claimed: [bool; N_SECTIONS],
+}
 
 #[derive(Debug)]
 pub struct SharedMatrix<
@@ -14,14 +77,18 @@ pub struct SharedMatrix<
     const SECTION_SIZE: usize,
     const N_SECTIONS: usize,
 > {
+    /// Only accessed through the sections until the computation has completed
     matrix: UnsafeCell<Matrix<'a, SIDE, SIZE, SECTION_SIZE, N_SECTIONS>>,
-    sections: UnsafeCell<
-        Option<[Option<MatrixSection<'a, SECTION_SIZE, SIDE, SIZE, N_SECTIONS>>; N_SECTIONS]>,
-    >,
-    initializing: AtomicBool,
-    initialized: AtomicBool,
-    section_available: [AtomicBool; N_SECTIONS],
+    /// None until the matrix is initialized; a ticket lock, so that no hart waits for a section
+    /// while the others keep claiming theirs
+    sections: TicketMutex<Option<Sections<'a, SIDE, SIZE, SECTION_SIZE, N_SECTIONS>>>,
     computation_completed: AtomicUsize,
+    /// Next section to be claimed in dynamic scheduling mode
+    next_section: AtomicUsize,
+    /// Id of the hart that computed each section in dynamic scheduling mode
+    processed_by: [AtomicUsize; N_SECTIONS],
+    /// Number of computations started again with `reset`
+    round: AtomicUsize,
 }
 
 impl<
@@ -40,69 +107,50 @@ where
     pub const fn new(init_value: Matrix<'a, SIDE, SIZE, SECTION_SIZE, N_SECTIONS>) -> Self {
         SharedMatrix {
             matrix: UnsafeCell::new(init_value),
-            sections: UnsafeCell::new(None),
-            initializing: AtomicBool::new(false),
-            initialized: AtomicBool::new(false),
-            section_available: [ATOMIC_TRUE; N_SECTIONS],
+            sections: TicketMutex::new(None),
             computation_completed: AtomicUsize::new(0),
+            next_section: AtomicUsize::new(0),
+            processed_by: [const { AtomicUsize::new(NOT_PROCESSED) }; N_SECTIONS],
+            round: AtomicUsize::new(0),
         }
     }
 
     /// Initializes the matrix (for now only sets the sections)
-    /// Call this function from at least one hart
-    /// The hart that will deal with the initialization will be decided by a race
+    /// Call this function from every hart before computing: the first one to get here does the
+    /// initialization, it is done for all of them when the call returns
     pub fn initialize(&self) {
-        // note that 2 flags are necessary, one to keep track if the initialization has started
-        // and one to keep track if the initialization has completed
-        if self
-            .initializing
-            .compare_exchange(
-                false,
-                true,
-                core::sync::atomic::Ordering::SeqCst,
-                core::sync::atomic::Ordering::SeqCst,
-            )
-            .is_err()
-        {
+        let mut sections = self.sections.lock();
+        if sections.is_some() {
             // some other thread has gotten here first
-            // they will be in charge of the initialization
             return;
         }
-        unsafe {
-            assert!((*self.sections.get()).is_none(), "API internal error");
-            (*self.sections.get()) = Some((*self.matrix.get()).sections_mut());
-        }
-        self.initialized
-            .store(true, core::sync::atomic::Ordering::SeqCst);
+        *sections = Some(Sections {
+            sections: unsafe { (*self.matrix.get()).sections_mut() },
+            claimed: [false; N_SECTIONS],
+        });
     }
 
     /// Gets an exclusive reference to a section of the matrix once they have been set
-    /// TODO: now we wait for all, we could spin on the specific one
-    fn get_section(
+    /// A section can only be claimed once
+    pub fn claim_section(
         &self,
         section_idx: usize,
-    ) -> MatrixSection<'a, SECTION_SIZE, SIDE, SIZE, N_SECTIONS> {
-        // spin until the matrix is initialized
-        while !self.initialized.load(core::sync::atomic::Ordering::SeqCst) {}
-        unsafe {
-            match (*self.sections.get()).as_mut() {
-                None => unreachable!("This cannot be none if section_set is true, unless the code paniced, in which case the program should have aborted"),
-                Some(sections) => {
-                    if self.section_available[section_idx]
-                        .compare_exchange(
-                            true,
-                            false,
-                            core::sync::atomic::Ordering::SeqCst,
-                            core::sync::atomic::Ordering::SeqCst,
-                        )
-                        .is_err()
-                    {
-                        panic!("Another thread already owns this section");
-                    }
-                    sections[section_idx].take().expect("By checking section_available, this should be unreachable")
-                }
-            }
+    ) -> Result<MatrixSection<'a, SECTION_SIZE, SIDE, SIZE, N_SECTIONS>, SharedMatrixError> {
+        if section_idx >= N_SECTIONS {
+            return Err(SharedMatrixError::OutOfRange {
+                index: section_idx,
+                sections: N_SECTIONS,
+            });
         }
+        let mut guard = self.sections.lock();
+        let sections = guard.as_mut().ok_or(SharedMatrixError::NotInitialized)?;
+        if sections.claimed[section_idx] {
+            return Err(SharedMatrixError::AlreadyClaimed(section_idx));
+        }
+        sections.claimed[section_idx] = true;
+        Ok(sections.sections[section_idx]
+            .take()
+            .expect("An unclaimed section is always there"))
     }
 
     fn notify_completed(
@@ -110,16 +158,16 @@ where
         section: MatrixSection<'a, SECTION_SIZE, SIDE, SIZE, N_SECTIONS>,
         section_idx: usize,
     ) {
+        // the section is given back before the computation counts as completed, it is
+        // borrowing from the matrix that `result` hands out
+        // it stays claimed until `reset` starts a new computation
+        self.sections
+            .lock()
+            .as_mut()
+            .expect("The computation has started, so the sections cannot be none")
+            .sections[section_idx] = Some(section);
         self.computation_completed
             .fetch_add(1, core::sync::atomic::Ordering::SeqCst);
-        unsafe {
-            (*self.sections.get())
-                .as_mut()
-                .expect("The computation has started, so the sections cannot be none")
-                [section_idx] = Some(section);
-        }
-        // TODO: uncomment the following line once we have a mechanism to start a new computation
-        // self.section_available[section_idx].store(true, core::sync::atomic::Ordering::SeqCst);
     }
 
     /*
@@ -135,15 +183,70 @@ where
     }
      */
 
+    /// Computes the section `section_idx` on the calling hart (static scheduling)
     pub fn compute(
         &'a self,
         compute_fn: impl FnOnce(&mut MatrixSection<'a, SECTION_SIZE, SIDE, SIZE, N_SECTIONS>),
         section_idx: usize,
-    ) {
-        let mut section = self.get_section(section_idx);
-        compute_fn(&mut section);
+    ) -> Result<(), SharedMatrixError> {
+        let mut section = self.claim_section(section_idx)?;
+        Self::run_section(compute_fn, &mut section);
         self.notify_completed(section, section_idx);
+        Ok(())
+    }
+
+    /// Runs `compute_fn` on `section`
+    /// With `user_mode` it runs in U-mode, where with `pmp` it can only write to the section
+    /// (see pmp.rs)
+    fn run_section(
+        compute_fn: impl FnOnce(&mut MatrixSection<'a, SECTION_SIZE, SIDE, SIZE, N_SECTIONS>),
+        section: &mut MatrixSection<'a, SECTION_SIZE, SIDE, SIZE, N_SECTIONS>,
+    ) {
+        #[cfg(feature = "pmp")]
+        crate::pmp::run_isolated(section.data_range(), || compute_fn(section));
+        #[cfg(all(feature = "user_mode", not(feature = "pmp")))]
+        crate::user::run(|| compute_fn(section));
+        #[cfg(not(feature = "user_mode"))]
+        compute_fn(section);
     }
+
+    /// Computes sections on the calling hart until none are left (dynamic scheduling)
+    /// Each hart claims the next free section from a shared counter, so N_SECTIONS
+    /// does not need to match the number of harts and a slow hart does not stall the others
+    /// Returns the number of sections computed by the calling hart, also on failure
+    pub fn compute_dynamic(
+        &'a self,
+        compute_fn: impl Fn(&mut MatrixSection<'a, SECTION_SIZE, SIDE, SIZE, N_SECTIONS>),
+        hart_id: usize,
+    ) -> Result<usize, DynamicError> {
+        // checked before taking an index: a section whose index was taken must be computed,
+        // or the computation never completes
+        // once initialized, a claim can only fail if `compute` claimed the section, and then
+        // that hart completes it
+        if self.sections.lock().is_none() {
+            return Err(DynamicError {
+                processed: 0,
+                error: SharedMatrixError::NotInitialized,
+            });
+        }
+        let mut processed = 0;
+        loop {
+            let section_idx = self
+                .next_section
+                .fetch_add(1, core::sync::atomic::Ordering::SeqCst);
+            if section_idx >= N_SECTIONS {
+                return Ok(processed);
+            }
+            let mut section = self
+                .claim_section(section_idx)
+                .map_err(|error| DynamicError { processed, error })?;
+            Self::run_section(&compute_fn, &mut section);
+            self.processed_by[section_idx].store(hart_id, core::sync::atomic::Ordering::SeqCst);
+            self.notify_completed(section, section_idx);
+            processed += 1;
+        }
+    }
+
     /*
     pub fn convolute(
         &'a self,
@@ -177,17 +280,139 @@ impl<
         const SIZE: usize,
         const SECTION_SIZE: usize,
         const N_SECTIONS: usize,
-    > Display for SharedMatrix<'a, SIDE, SIZE, SECTION_SIZE, N_SECTIONS>
+    > SharedMatrix<'a, SIDE, SIZE, SECTION_SIZE, N_SECTIONS>
 {
-    /// This spins until the matrix is available (i.e. all computations are completed)
+    /// This spins until all sections have been computed
     /// If a thread does not notify that it has completed its computation, this will spin forever
-    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+    pub fn wait_completed(&self) {
         while self
             .computation_completed
             .load(core::sync::atomic::Ordering::SeqCst)
-            != N_HARTS
-        {}
-        unsafe { write!(f, "{:?}", (*self.matrix.get())) }
+            != N_SECTIONS
+        {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// This spins until all sections have been computed, then returns the result
+    pub fn result(&self) -> &Matrix<'a, SIDE, SIZE, SECTION_SIZE, N_SECTIONS> {
+        self.wait_completed();
+        unsafe { &*self.matrix.get() }
+    }
+
+    /// Starts a new computation once the current one has completed, from a single hart
+    /// The result is cleared and every section can be claimed again; the other harts wait for
+    /// it with `wait_round`
+    pub fn reset(&self) {
+        self.wait_completed();
+        if let Some(sections) = self.sections.lock().as_mut() {
+            sections
+                .sections
+                .iter_mut()
+                .flatten()
+                .for_each(|section| section.clear());
+            sections.claimed = [false; N_SECTIONS];
+        }
+        self.processed_by
+            .iter()
+            .for_each(|owner| owner.store(NOT_PROCESSED, core::sync::atomic::Ordering::SeqCst));
+        self.next_section
+            .store(0, core::sync::atomic::Ordering::SeqCst);
+        self.computation_completed
+            .store(0, core::sync::atomic::Ordering::SeqCst);
+        self.round
+            .fetch_add(1, core::sync::atomic::Ordering::SeqCst);
+    }
+
+    /// Spins until `reset` has been called `round` times
+    pub fn wait_round(&self, round: usize) {
+        while self.round.load(core::sync::atomic::Ordering::SeqCst) < round {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Returns the result if all sections have been computed, without waiting
+    pub fn try_result(&self) -> Option<&Matrix<'a, SIDE, SIZE, SECTION_SIZE, N_SECTIONS>> {
+        (self
+            .computation_completed
+            .load(core::sync::atomic::Ordering::SeqCst)
+            == N_SECTIONS)
+            .then(|| unsafe { &*self.matrix.get() })
+    }
+
+    /// Counters of the lock that hands out the sections
+    pub fn lock_stats(&self) -> LockStats {
+        self.sections.stats()
+    }
+
+    /// Returns how many sections have been computed by `hart_id` with `compute_dynamic`
+    /// Only meaningful once the computation has completed, see `wait_completed`
+    pub fn sections_processed(&self, hart_id: usize) -> usize {
+        self.processed_by
+            .iter()
+            .filter(|owner| owner.load(core::sync::atomic::Ordering::SeqCst) == hart_id)
+            .count()
+    }
+}
+
+impl<
+        'a,
+        const SIDE: usize,
+        const SIZE: usize,
+        const SECTION_SIZE: usize,
+        const N_SECTIONS: usize,
+    > Display for SharedMatrix<'a, SIDE, SIZE, SECTION_SIZE, N_SECTIONS>
+{
+    /// Never waits for the computation: formatting usually happens with the console lock held,
+    /// so wait with `result` first
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.try_result() {
+            Some(result) => write!(f, "{:?}", result),
+            None => write!(
+                f,
+                "<the computation is in progress, {}/{} sections completed>",
+                self.computation_completed
+                    .load(core::sync::atomic::Ordering::SeqCst),
+                N_SECTIONS
+            ),
+        }
+    }
+}
+
+impl<
+        'a,
+        const SIDE: usize,
+        const SIZE: usize,
+        const SECTION_SIZE: usize,
+        const N_SECTIONS: usize,
+    > Watchable for SharedMatrix<'a, SIDE, SIZE, SECTION_SIZE, N_SECTIONS>
+{
+    fn report(&self) {
+        print!("section_available:");
+        // the watchdog interrupted a hart, which may be the one holding the lock
+        match self.sections.try_lock() {
+            Some(guard) => match guard.as_ref() {
+                Some(sections) => {
+                    for claimed in sections.claimed {
+                        print!(" {}", !claimed as u8);
+                    }
+                }
+                None => print!(" not initialized"),
+            },
+            None => print!(" locked"),
+        }
+        println!();
+        let stats = self.sections.stats();
+        println!(
+            "section lock: {} acquisitions, {} contended",
+            stats.acquisitions, stats.contended
+        );
+        println!(
+            "computation_completed: {}/{}",
+            self.computation_completed
+                .load(core::sync::atomic::Ordering::SeqCst),
+            N_SECTIONS
+        );
     }
 }
 
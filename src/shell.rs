@@ -0,0 +1,320 @@
+// shell.rs
+// Interactive command shell over the serial console, run by hart 0
+// The other harts wait for the jobs started by `run` and compute their share of the result,
+// so benchmarks can be repeated with different sizes and hart counts without reflashing
+
+use crate::console::{self, Console};
+use crate::dyn_matrix::{DynMatrix, DynMatrixSection};
+use crate::matrix::Number;
+use crate::report::{self, Run, Timing, Verification};
+use crate::{heap, shutdown, stack};
+use crate::{print, println};
+use core::cell::UnsafeCell;
+use core::fmt::Display;
+use core::str::SplitWhitespace;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const LINE_SIZE: usize = 128;
+
+/// A benchmark that can be started from the shell
+pub struct Benchmark {
+    pub name: &'static str,
+    /// Matrix side used when `run` is not given one
+    pub default_side: usize,
+    /// Builds the two operands for a result matrix of side `side`
+    pub inputs: fn(side: usize) -> (DynMatrix, DynMatrix),
+    /// Accumulates the operation on the operands into a section of the result
+    pub kernel: fn(section: &mut DynMatrixSection, a: &DynMatrix, b: &DynMatrix),
+}
+
+static BENCHMARKS: [&Benchmark; 2] = [
+    &crate::matrix_multiplication::benchmark::BENCHMARK,
+    &crate::convolution::benchmark::BENCHMARK,
+];
+
+#[no_mangle]
+extern "C" fn main(hart_id: usize) {
+    if hart_id == 0 {
+        Shell::new().run();
+    } else {
+        worker(hart_id);
+    }
+}
+
+#[derive(Debug)]
+enum CommandError {
+    UnknownCommand,
+    UnknownBenchmark,
+    MissingArgument(&'static str),
+    InvalidArgument(&'static str),
+    TooLarge { required: usize, free: usize },
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            CommandError::UnknownCommand => write!(f, "unknown command, try `help`"),
+            CommandError::UnknownBenchmark => write!(f, "unknown benchmark, try `list`"),
+            CommandError::MissingArgument(name) => write!(f, "missing argument <{}>", name),
+            CommandError::InvalidArgument(name) => write!(f, "invalid argument <{}>", name),
+            CommandError::TooLarge { required, free } => write!(
+                f,
+                "the matrices need {} KiB but only {} KiB of heap are free",
+                required / 1024,
+                free / 1024
+            ),
+        }
+    }
+}
+
+struct Shell {
+    /// Harts taking part in `run`, hart 0 included
+    harts: usize,
+    /// Check the result of `run` against a single hart computation
+    verify: bool,
+}
+
+impl Shell {
+    fn new() -> Self {
+        Shell {
+            harts: crate::n_harts(),
+            verify: true,
+        }
+    }
+
+    fn run(&mut self) -> ! {
+        println!("Benchmark shell, type `help` for the list of commands");
+        let mut buffer = [0; LINE_SIZE];
+        loop {
+            print!("> ");
+            let line = Console::get().read_line(&mut buffer);
+            let mut words = line.split_whitespace();
+            let Some(command) = words.next() else {
+                continue;
+            };
+            if let Err(error) = self.execute(command, words) {
+                println!("Error: {}", error);
+            }
+        }
+    }
+
+    fn execute(&mut self, command: &str, mut args: SplitWhitespace) -> Result<(), CommandError> {
+        match command {
+            "help" => {
+                println!("list                                 list the benchmarks");
+                println!("run <benchmark> [size] [iterations] run a benchmark");
+                println!("harts [n]                            show or set the harts used by run");
+                println!("verify [on|off]                      show or set result verification");
+                println!(
+                    "prefix [on|off]                      show or set the hart/time line prefix"
+                );
+                println!("reboot                               restart the machine");
+            }
+            "list" => {
+                for benchmark in BENCHMARKS {
+                    println!(
+                        "{} (default size {})",
+                        benchmark.name, benchmark.default_side
+                    );
+                }
+            }
+            "run" => {
+                let name = args
+                    .next()
+                    .ok_or(CommandError::MissingArgument("benchmark"))?;
+                let benchmark = BENCHMARKS
+                    .iter()
+                    .find(|benchmark| benchmark.name == name)
+                    .ok_or(CommandError::UnknownBenchmark)?;
+                let side = parse_number(args.next(), "size")?.unwrap_or(benchmark.default_side);
+                let iterations = parse_number(args.next(), "iterations")?.unwrap_or(1);
+                if side == 0 || iterations == 0 || iterations > u32::MAX as usize {
+                    return Err(CommandError::InvalidArgument("size/iterations"));
+                }
+                self.run_benchmark(benchmark, side, iterations)?;
+            }
+            "harts" => {
+                if let Some(harts) = parse_number(args.next(), "n")? {
+                    if harts == 0 || harts > crate::n_harts() {
+                        return Err(CommandError::InvalidArgument("n"));
+                    }
+                    self.harts = harts;
+                }
+                println!("Using {} of {} harts", self.harts, crate::n_harts());
+            }
+            "verify" => {
+                match args.next() {
+                    Some("on") => self.verify = true,
+                    Some("off") => self.verify = false,
+                    Some(_) => return Err(CommandError::InvalidArgument("on|off")),
+                    None => {}
+                }
+                println!("Verification {}", if self.verify { "on" } else { "off" });
+            }
+            "prefix" => {
+                match args.next() {
+                    Some("on") => console::set_line_prefix(true),
+                    Some("off") => console::set_line_prefix(false),
+                    Some(_) => return Err(CommandError::InvalidArgument("on|off")),
+                    None => {}
+                }
+                println!(
+                    "Line prefix {}",
+                    if console::line_prefix() { "on" } else { "off" }
+                );
+            }
+            "reboot" => shutdown::reboot(),
+            _ => return Err(CommandError::UnknownCommand),
+        }
+        Ok(())
+    }
+
+    fn run_benchmark(
+        &self,
+        benchmark: &Benchmark,
+        side: usize,
+        iterations: usize,
+    ) -> Result<(), CommandError> {
+        // a, b, the result and the reference for the verification
+        let matrices = if self.verify { 4 } else { 3 };
+        let required = side
+            .checked_mul(side)
+            .and_then(|size| size.checked_mul(matrices * core::mem::size_of::<Number>()))
+            .unwrap_or(usize::MAX);
+        let free = heap::stats().1;
+        if required > free {
+            return Err(CommandError::TooLarge { required, free });
+        }
+
+        println!(
+            "{}: size {}, {} harts, {} iterations",
+            benchmark.name, side, self.harts, iterations
+        );
+        let (a, b) = (benchmark.inputs)(side);
+        let mut c = DynMatrix::zeroes(side, side);
+        let mut timing = Timing::new();
+        for _ in 0..iterations {
+            // the kernels accumulate into the result
+            c.data_mut().fill(0);
+            let t = crate::time();
+            compute_parallel(benchmark, &a, &b, &mut c, self.harts);
+            timing.record(crate::time() - t);
+        }
+        println!("Time: {}", timing);
+
+        let verification = if self.verify {
+            let mut reference = DynMatrix::zeroes(side, side);
+            reference
+                .sections_mut(side * side)
+                .for_each(|mut section| (benchmark.kernel)(&mut section, &a, &b));
+            Verification::from_check(c == reference)
+        } else {
+            Verification::Skipped
+        };
+        println!("Verification: {}", verification.as_str());
+
+        report::emit(&Run {
+            benchmark: benchmark.name,
+            size: side,
+            harts: self.harts,
+            // sections are split statically between the harts
+            mode: "parallel",
+            timing,
+            verification,
+        });
+        stack::report();
+        Ok(())
+    }
+}
+
+/// Parses an optional numeric argument
+fn parse_number(arg: Option<&str>, name: &'static str) -> Result<Option<usize>, CommandError> {
+    arg.map(|arg| arg.parse().map_err(|_| CommandError::InvalidArgument(name)))
+        .transpose()
+}
+
+/// Work published by hart 0 for the harts taking part in a run
+#[derive(Clone, Copy)]
+struct Job {
+    kernel: fn(&mut DynMatrixSection, &DynMatrix, &DynMatrix),
+    a: *const DynMatrix,
+    b: *const DynMatrix,
+    result: *mut Number,
+    rows: usize,
+    cols: usize,
+    harts: usize,
+}
+
+impl Job {
+    /// Computes the share of the result of `hart_id`, one section per hart
+    fn compute(&self, hart_id: usize) {
+        if hart_id >= self.harts {
+            return;
+        }
+        let size = self.rows * self.cols;
+        let section_size = size.div_ceil(self.harts);
+        let offset = (hart_id * section_size).min(size);
+        let len = section_size.min(size - offset);
+        // sections are disjoint and the result is not accessed by hart 0 until every hart is done
+        let section_data = unsafe { core::slice::from_raw_parts_mut(self.result.add(offset), len) };
+        let mut section = DynMatrixSection::new(section_data, self.rows, self.cols, offset);
+        unsafe { (self.kernel)(&mut section, &*self.a, &*self.b) };
+    }
+}
+
+struct JobQueue {
+    job: UnsafeCell<Option<Job>>,
+    /// Incremented by hart 0 every time a new job is published
+    generation: AtomicUsize,
+    /// Number of worker harts that are done with the current job
+    finished: AtomicUsize,
+}
+
+unsafe impl Sync for JobQueue {}
+
+static JOBS: JobQueue = JobQueue {
+    job: UnsafeCell::new(None),
+    generation: AtomicUsize::new(0),
+    finished: AtomicUsize::new(0),
+};
+
+/// Computes `c` on the first `harts` harts, hart 0 included
+/// Every worker acknowledges the job, even if it does not take part, before this returns
+fn compute_parallel(
+    benchmark: &Benchmark,
+    a: &DynMatrix,
+    b: &DynMatrix,
+    c: &mut DynMatrix,
+    harts: usize,
+) {
+    let job = Job {
+        kernel: benchmark.kernel,
+        a,
+        b,
+        result: c.data_mut().as_mut_ptr(),
+        rows: c.rows(),
+        cols: c.cols(),
+        harts,
+    };
+    unsafe { *JOBS.job.get() = Some(job) };
+    JOBS.finished.store(0, Ordering::SeqCst);
+    JOBS.generation.fetch_add(1, Ordering::SeqCst);
+    job.compute(0);
+    while JOBS.finished.load(Ordering::SeqCst) != crate::n_harts() - 1 {
+        core::hint::spin_loop();
+    }
+}
+
+fn worker(hart_id: usize) -> ! {
+    let mut generation = 0;
+    loop {
+        while JOBS.generation.load(Ordering::SeqCst) == generation {
+            core::hint::spin_loop();
+        }
+        generation = JOBS.generation.load(Ordering::SeqCst);
+        if let Some(job) = unsafe { *JOBS.job.get() } {
+            job.compute(hart_id);
+        }
+        JOBS.finished.fetch_add(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,71 @@
+// shutdown.rs
+// Stops the harts and terminates the run through the QEMU test finisher (sifive,test0)
+// On boards without it the SBI system reset is used with `sbi`, otherwise the calling hart
+// is halted instead
+
+use crate::console::Console;
+use crate::{clint, machine};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Exit status of a run stopped by a panic
+pub const EXIT_PANIC: u16 = 1;
+/// Exit status of a run stopped by the watchdog
+pub const EXIT_WATCHDOG: u16 = 2;
+
+/// Set by the first hart that fails, a software interrupt is then a request to stop
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+/// Claims the right to stop the run
+/// Returns false if another hart is already stopping it
+pub fn request_stop() -> bool {
+    !STOPPING.swap(true, Ordering::SeqCst)
+}
+
+pub fn stop_requested() -> bool {
+    STOPPING.load(Ordering::SeqCst)
+}
+
+/// Sends a stop IPI to every hart other than `hart_id`, call `request_stop` first
+/// The harts halt in the trap handler, or in kinit if they were never released
+pub fn stop_other_harts(hart_id: usize) {
+    for hart in (0..crate::n_harts()).filter(|&hart| hart != hart_id) {
+        clint::send_ipi(hart);
+    }
+}
+
+const FINISHER_PASS: u32 = 0x5555;
+const FINISHER_FAIL: u32 = 0x3333;
+const FINISHER_RESET: u32 = 0x7777;
+
+fn finish(value: u32) -> ! {
+    Console::get().flush();
+    if let Some(base) = machine::get().test_base {
+        unsafe { (base as *mut u32).write_volatile(value) };
+    }
+    #[cfg(feature = "sbi")]
+    {
+        use crate::sbi;
+        let (reset_type, reason) = match value {
+            FINISHER_PASS => (sbi::RESET_SHUTDOWN, sbi::REASON_NONE),
+            FINISHER_RESET => (sbi::RESET_COLD_REBOOT, sbi::REASON_NONE),
+            _ => (sbi::RESET_SHUTDOWN, sbi::REASON_SYSTEM_FAILURE),
+        };
+        sbi::system_reset(reset_type, reason);
+    }
+    crate::abort()
+}
+
+/// Exits QEMU with status 0
+pub fn exit_success() -> ! {
+    finish(FINISHER_PASS)
+}
+
+/// Restarts the machine from the reset vector
+pub fn reboot() -> ! {
+    finish(FINISHER_RESET)
+}
+
+/// Exits QEMU with status `code`
+pub fn exit_failure(code: u16) -> ! {
+    finish(FINISHER_FAIL | (code as u32) << 16)
+}
@@ -0,0 +1,176 @@
+// sifive_uart.rs
+// SiFive UART driver (FU540 and the QEMU sifive_u machine)
+// Registers are 32 bits wide, the FIFOs hold 8 characters each
+
+use crate::serial::{Serial, TxBuffer};
+use core::fmt::Error;
+use core::fmt::Write;
+
+/// Number of polls of the transmit FIFO before giving up, see uart.rs
+const TX_TIMEOUT: usize = 100_000;
+
+// Register offsets
+const TXDATA: usize = 0x00;
+const RXDATA: usize = 0x04;
+const TXCTRL: usize = 0x08;
+const RXCTRL: usize = 0x0c;
+const IE: usize = 0x10;
+const DIV: usize = 0x18;
+
+/// txdata: the FIFO cannot take a character, rxdata: no character was received
+const FIFO_FLAG: u32 = 1 << 31;
+
+// txctrl and rxctrl bits
+const CTRL_ENABLE: u32 = 1 << 0;
+const CTRL_COUNT_SHIFT: u32 = 16;
+
+// Interrupt enable bits, the watermarks are set by the txcnt and rxcnt fields
+const IE_TXWM: u32 = 1 << 0;
+const IE_RXWM: u32 = 1 << 1;
+
+pub struct SifiveUart {
+    base_address: usize,
+    clock_hz: u32,
+    baud_rate: u32,
+    /// Characters are queued here and sent from the transmit watermark interrupt
+    tx_buffer: Option<TxBuffer>,
+}
+
+impl Write for SifiveUart {
+    fn write_str(&mut self, out: &str) -> Result<(), Error> {
+        for c in out.bytes() {
+            self.put(c);
+        }
+        Ok(())
+    }
+}
+
+impl SifiveUart {
+    /// `clock_hz` is the bus clock (tlclk) feeding the UART, used to derive the divisor
+    pub fn new(base_address: usize, clock_hz: u32, baud_rate: u32) -> Self {
+        SifiveUart {
+            base_address,
+            clock_hz,
+            baud_rate,
+            tx_buffer: None,
+        }
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.base_address + offset) as *const u32).read_volatile() }
+    }
+
+    fn write(&mut self, offset: usize, value: u32) {
+        unsafe { ((self.base_address + offset) as *mut u32).write_volatile(value) }
+    }
+
+    fn transmit_ready(&self) -> bool {
+        self.read(TXDATA) & FIFO_FLAG == 0
+    }
+
+    /// Waits (for a bounded time) until the transmit FIFO has room
+    /// Returns false on timeout
+    fn wait_transmit_ready(&self) -> bool {
+        for _ in 0..TX_TIMEOUT {
+            if self.transmit_ready() {
+                return true;
+            }
+            core::hint::spin_loop();
+        }
+        false
+    }
+
+    /// Moves characters from the buffer to the transmit FIFO until either is exhausted
+    fn send_buffered(&mut self) {
+        while self.transmit_ready() {
+            match self.tx_buffer.as_mut().and_then(TxBuffer::pop) {
+                Some(c) => self.write(TXDATA, c as u32),
+                None => break,
+            }
+        }
+    }
+
+    /// The transmit watermark interrupt is only enabled while there are characters waiting
+    fn update_tx_interrupt(&mut self) {
+        let pending = self.tx_buffer.as_ref().is_some_and(|tx| !tx.is_empty());
+        let ie = self.read(IE);
+        let new_ie = if pending { ie | IE_TXWM } else { ie & !IE_TXWM };
+        if new_ie != ie {
+            self.write(IE, new_ie);
+        }
+    }
+}
+
+impl Serial for SifiveUart {
+    fn init(&mut self) {
+        // 8N1 is the only frame format, one stop bit is the reset value of nstop
+        // The transmit watermark fires once the FIFO is empty (fewer than 1 character),
+        // the receive one as soon as a character is waiting (more than 0)
+        self.write(TXCTRL, CTRL_ENABLE | (1 << CTRL_COUNT_SHIFT));
+        self.write(RXCTRL, CTRL_ENABLE);
+        self.write(IE, IE_RXWM);
+
+        self.set_baud_rate(self.baud_rate);
+
+        write!(self, "UART initialized\r\n").unwrap();
+    }
+
+    /// baud rate = clock_hz / (div + 1)
+    fn set_baud_rate(&mut self, baud_rate: u32) {
+        self.baud_rate = baud_rate;
+        let divisor = self.clock_hz.div_ceil(baud_rate).max(1) - 1;
+        self.write(DIV, divisor);
+    }
+
+    fn baud_rate(&self) -> u32 {
+        self.baud_rate
+    }
+
+    fn enable_buffered_tx(&mut self) {
+        self.tx_buffer = Some(TxBuffer::new());
+    }
+
+    fn put(&mut self, c: u8) {
+        if self.tx_buffer.is_none() {
+            // after a timeout the character is written anyway, at worst it is lost
+            self.wait_transmit_ready();
+            self.write(TXDATA, c as u32);
+            return;
+        }
+        if self.tx_buffer.as_ref().is_some_and(TxBuffer::is_full) {
+            // buffer full, make room by waiting for the FIFO
+            self.wait_transmit_ready();
+            self.send_buffered();
+        }
+        if let Some(tx) = self.tx_buffer.as_mut() {
+            tx.push(c);
+        }
+        self.send_buffered();
+        self.update_tx_interrupt();
+    }
+
+    fn handle_interrupt(&mut self) {
+        self.send_buffered();
+        self.update_tx_interrupt();
+    }
+
+    fn flush(&mut self) {
+        while self.tx_buffer.as_ref().is_some_and(|tx| !tx.is_empty()) {
+            if !self.wait_transmit_ready() {
+                break;
+            }
+            self.send_buffered();
+        }
+        self.update_tx_interrupt();
+    }
+
+    fn get(&mut self) -> Option<u8> {
+        // reading rxdata pops the FIFO, the empty flag and the data come in the same word
+        let rxdata = self.read(RXDATA);
+        if rxdata & FIFO_FLAG != 0 {
+            None
+        } else {
+            Some(rxdata as u8)
+        }
+    }
+}
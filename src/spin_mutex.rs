@@ -0,0 +1,212 @@
+// spin_mutex.rs
+// Mutual exclusion between harts by spinning
+// `SpinMutex<T>` gives access to its value through a guard that releases the lock when it is
+// dropped. Interrupts are masked while the lock is held, so an interrupt handler can never
+// spin on a lock held by the code it interrupted
+// The lock is a test-and-test-and-set flag by default, `TicketMutex` serves the harts in the
+// order they arrive instead, so that none of them can be starved
+// Every mutex counts how often it was taken and how often a hart had to wait for it
+// The guards use mstatus, so a mutex cannot be taken in U-mode (see user.rs)
+
+use crate::trap;
+use core::cell::UnsafeCell;
+use core::fmt::Debug;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// The lock itself, without the data it protects
+pub trait RawLock {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const UNLOCKED: Self;
+
+    /// Spins until the lock is taken, returns true if it had to wait
+    fn lock(&self) -> bool;
+    /// Takes the lock if it is free
+    fn try_lock(&self) -> bool;
+    /// # Safety
+    /// Must only be called by the holder of the lock
+    unsafe fn unlock(&self);
+}
+
+/// Test-and-test-and-set lock, the waiting harts only read the flag until it is released
+pub struct Spin(AtomicBool);
+
+impl RawLock for Spin {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const UNLOCKED: Self = Spin(AtomicBool::new(false));
+
+    fn lock(&self) -> bool {
+        let mut waited = false;
+        while self
+            .0
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            waited = true;
+            while self.0.load(Ordering::Relaxed) {
+                core::hint::spin_loop();
+            }
+        }
+        waited
+    }
+
+    fn try_lock(&self) -> bool {
+        self.0
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// Ticket lock, first come first served
+pub struct Ticket {
+    next: AtomicUsize,
+    serving: AtomicUsize,
+}
+
+impl RawLock for Ticket {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const UNLOCKED: Self = Ticket {
+        next: AtomicUsize::new(0),
+        serving: AtomicUsize::new(0),
+    };
+
+    fn lock(&self) -> bool {
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut waited = false;
+        while self.serving.load(Ordering::Acquire) != ticket {
+            waited = true;
+            core::hint::spin_loop();
+        }
+        waited
+    }
+
+    fn try_lock(&self) -> bool {
+        let serving = self.serving.load(Ordering::Relaxed);
+        self.next
+            .compare_exchange(serving, serving + 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        // only the holder writes `serving`
+        let serving = self.serving.load(Ordering::Relaxed);
+        self.serving.store(serving + 1, Ordering::Release);
+    }
+}
+
+/// Counters of a mutex since it was created
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockStats {
+    pub acquisitions: usize,
+    /// Acquisitions that found the lock taken and had to wait
+    pub contended: usize,
+}
+
+pub struct SpinMutex<T, L: RawLock = Spin> {
+    lock: L,
+    acquisitions: AtomicUsize,
+    contended: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+/// Fair variant of `SpinMutex`
+pub type TicketMutex<T> = SpinMutex<T, Ticket>;
+
+unsafe impl<T: Send, L: RawLock> Sync for SpinMutex<T, L> {}
+
+impl<T, L: RawLock> SpinMutex<T, L> {
+    pub const fn new(value: T) -> Self {
+        SpinMutex {
+            lock: L::UNLOCKED,
+            acquisitions: AtomicUsize::new(0),
+            contended: AtomicUsize::new(0),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Masks interrupts and spins until the lock is taken
+    pub fn lock(&self) -> SpinMutexGuard<'_, T, L> {
+        let interrupts = trap::disable_interrupts();
+        if self.lock.lock() {
+            self.contended.fetch_add(1, Ordering::Relaxed);
+        }
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        SpinMutexGuard {
+            mutex: self,
+            interrupts,
+        }
+    }
+
+    /// Takes the lock if it is free, without waiting
+    pub fn try_lock(&self) -> Option<SpinMutexGuard<'_, T, L>> {
+        let interrupts = trap::disable_interrupts();
+        if !self.lock.try_lock() {
+            trap::restore_interrupts(interrupts);
+            return None;
+        }
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        Some(SpinMutexGuard {
+            mutex: self,
+            interrupts,
+        })
+    }
+
+    pub fn stats(&self) -> LockStats {
+        LockStats {
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            contended: self.contended.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// The value, without taking the lock
+    /// Only meant for code that takes over from a guard it interrupted on the same hart, e.g.
+    /// the console printing a panic (see console.rs)
+    pub fn data_ptr(&self) -> *mut T {
+        self.data.get()
+    }
+}
+
+impl<T: Debug, L: RawLock> Debug for SpinMutex<T, L> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_tuple("SpinMutex").field(&*guard).finish(),
+            None => f.write_str("SpinMutex(<locked>)"),
+        }
+    }
+}
+
+/// Access to the value of a `SpinMutex`, the lock is released and the interrupts are restored
+/// when it is dropped
+pub struct SpinMutexGuard<'a, T, L: RawLock = Spin> {
+    mutex: &'a SpinMutex<T, L>,
+    interrupts: bool,
+}
+
+impl<T, L: RawLock> Deref for SpinMutexGuard<'_, T, L> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T, L: RawLock> DerefMut for SpinMutexGuard<'_, T, L> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T, L: RawLock> Drop for SpinMutexGuard<'_, T, L> {
+    fn drop(&mut self) {
+        unsafe { self.mutex.lock.unlock() };
+        trap::restore_interrupts(self.interrupts);
+    }
+}
@@ -0,0 +1,111 @@
+// stack.rs
+// Overflow detection and usage of the per-hart stacks
+// Hart n runs on the n-th `params::STACK_SIZE` slot below `_stack_end` (see boot.s). Before the
+// other harts are released, hart 0 paints the slots with a pattern, and the bottom of each slot
+// with a canary. A smashed canary means the hart ran past its slot, into the stack of the next
+// hart (or into the bss for the last one); the paint that is left shows how deep each stack
+// has been, its high-water mark
+
+use crate::machine::MAX_HARTS;
+use crate::params::STACK_SIZE;
+use crate::{print, println};
+use core::ops::Range;
+
+extern "C" {
+    static _stack_start: u8;
+    static _stack_end: u8;
+}
+
+const PAINT: u64 = 0x5041_494e_5441_434b;
+const CANARY: u64 = 0xc0de_ca4a_57ac_c0de;
+/// Size of the canary at the bottom of each slot
+const CANARY_SIZE: usize = 64;
+
+/// Stack slot of `hart_id`
+fn slot(hart_id: usize) -> Range<usize> {
+    let top = core::ptr::addr_of!(_stack_end) as usize - hart_id * STACK_SIZE;
+    top - STACK_SIZE..top
+}
+
+/// Fills [start, end) with `pattern`, without using the stack
+unsafe fn fill(start: usize, end: usize, pattern: u64) {
+    if start < end {
+        core::arch::asm!(
+            "1:",
+            "sd {pattern}, 0({address})",
+            "addi {address}, {address}, 8",
+            "bltu {address}, {end}, 1b",
+            address = inout(reg) start => _,
+            end = in(reg) end,
+            pattern = in(reg) pattern,
+        );
+    }
+}
+
+/// Paints the stacks and sets the canaries
+/// # Safety
+/// Must be called by hart 0 before any other hart is released
+pub unsafe fn init() {
+    let region = slot(0).end - core::ptr::addr_of!(_stack_start) as usize;
+    assert_eq!(
+        region,
+        STACK_SIZE * MAX_HARTS,
+        "the stack region of the linker script does not match BENCH_STACK_SIZE"
+    );
+    let sp: usize;
+    core::arch::asm!("mv {}, sp", out(reg) sp);
+    for hart in 0..MAX_HARTS {
+        let slot = slot(hart);
+        fill(slot.start, slot.start + CANARY_SIZE, CANARY);
+        // hart 0 is running on its slot, which is only painted below the current frame
+        let end = if hart == 0 { sp & !7 } else { slot.end };
+        fill(slot.start + CANARY_SIZE, end, PAINT);
+    }
+}
+
+fn words(range: Range<usize>) -> impl Iterator<Item = u64> {
+    range
+        .step_by(8)
+        .map(|address| unsafe { (address as *const u64).read_volatile() })
+}
+
+/// false if `hart_id` has overflowed its stack
+pub fn canary_intact(hart_id: usize) -> bool {
+    let bottom = slot(hart_id).start;
+    words(bottom..bottom + CANARY_SIZE).all(|word| word == CANARY)
+}
+
+/// Deepest use of the stack of `hart_id` so far, in bytes
+pub fn high_water(hart_id: usize) -> usize {
+    let slot = slot(hart_id);
+    let untouched = words(slot.start + CANARY_SIZE..slot.end)
+        .take_while(|&word| word == PAINT)
+        .count()
+        * 8;
+    STACK_SIZE - CANARY_SIZE - untouched
+}
+
+/// Prints the high-water marks of the harts that took part in the benchmark,
+/// and fails the run if any of them has overflowed its stack
+pub fn report() {
+    let mut overflowed = None;
+    for hart in 0..crate::running_harts() {
+        if canary_intact(hart) {
+            println!(
+                "Stack: hart {}: {} of {} bytes used",
+                hart,
+                high_water(hart),
+                STACK_SIZE
+            );
+        } else {
+            println!("Stack: hart {}: overflowed", hart);
+            overflowed.get_or_insert(hart);
+        }
+    }
+    if let Some(hart) = overflowed {
+        panic!(
+            "Hart {} overflowed its {} byte stack (see BENCH_STACK_SIZE)",
+            hart, STACK_SIZE
+        );
+    }
+}
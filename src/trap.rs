@@ -0,0 +1,227 @@
+// trap.rs
+// Trap handling, in machine mode or in supervisor mode with `sbi` (see privilege.rs)
+// asm_trap_vector (asm/trap.s) saves the registers into the trap frame of the hart
+// and calls m_trap, which reports exceptions and routes interrupts to the registered handlers
+
+use crate::clint;
+use crate::machine::MAX_HARTS;
+use crate::privilege;
+use crate::shutdown;
+#[cfg(feature = "user_mode")]
+use crate::user;
+use crate::{print, println};
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+const TRAP_STACK_SIZE: usize = 16 * 1024;
+
+/// Interrupt bit of mcause
+const MCAUSE_INTERRUPT: usize = 1 << (usize::BITS - 1);
+
+/// Layout shared with asm/trap.s and asm/user.s, do not reorder the fields
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct TrapFrame {
+    pub regs: [usize; 32],
+    pub fregs: [usize; 32],
+    /// Top of the stack the handler runs on (offset 512)
+    pub trap_stack: usize,
+    pub hart_id: usize,
+    /// Stack pointer of the hart when it entered U-mode, see user.rs (offset 528)
+    pub kernel_sp: usize,
+}
+
+impl TrapFrame {
+    const fn zero() -> Self {
+        TrapFrame {
+            regs: [0; 32],
+            fregs: [0; 32],
+            trap_stack: 0,
+            hart_id: 0,
+            kernel_sp: 0,
+        }
+    }
+}
+
+#[repr(C, align(16))]
+struct TrapStack([u8; TRAP_STACK_SIZE]);
+
+static mut TRAP_FRAMES: [TrapFrame; MAX_HARTS] = [TrapFrame::zero(); MAX_HARTS];
+static mut TRAP_STACKS: [TrapStack; MAX_HARTS] =
+    [const { TrapStack([0; TRAP_STACK_SIZE]) }; MAX_HARTS];
+
+/// Interrupts of the privilege level, the value is the mcause code and the bit in mie/mip
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    Software = privilege::SOFTWARE_INTERRUPT,
+    Timer = privilege::TIMER_INTERRUPT,
+    External = privilege::EXTERNAL_INTERRUPT,
+}
+
+impl Interrupt {
+    fn from_code(code: usize) -> Option<Self> {
+        match code {
+            privilege::SOFTWARE_INTERRUPT => Some(Interrupt::Software),
+            privilege::TIMER_INTERRUPT => Some(Interrupt::Timer),
+            privilege::EXTERNAL_INTERRUPT => Some(Interrupt::External),
+            _ => None,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Interrupt::Software => 0,
+            Interrupt::Timer => 1,
+            Interrupt::External => 2,
+        }
+    }
+}
+
+/// Called with the id of the hart that took the interrupt
+pub type InterruptHandler = fn(hart_id: usize);
+
+static HANDLERS: [AtomicPtr<()>; 3] = [const { AtomicPtr::new(core::ptr::null_mut()) }; 3];
+
+/// Registers the handler of `interrupt` for all harts, replacing the previous one
+pub fn register_handler(interrupt: Interrupt, handler: InterruptHandler) {
+    HANDLERS[interrupt.index()].store(handler as *mut (), Ordering::SeqCst);
+}
+
+fn handler(interrupt: Interrupt) -> Option<InterruptHandler> {
+    let handler = HANDLERS[interrupt.index()].load(Ordering::SeqCst);
+    if handler.is_null() {
+        None
+    } else {
+        Some(unsafe { core::mem::transmute::<*mut (), InterruptHandler>(handler) })
+    }
+}
+
+fn exception_name(code: usize) -> &'static str {
+    match code {
+        0 => "Instruction address misaligned",
+        1 => "Instruction access fault",
+        2 => "Illegal instruction",
+        3 => "Breakpoint",
+        4 => "Load address misaligned",
+        5 => "Load access fault",
+        6 => "Store/AMO address misaligned",
+        7 => "Store/AMO access fault",
+        8 => "Environment call from U-mode",
+        9 => "Environment call from S-mode",
+        11 => "Environment call from M-mode",
+        12 => "Instruction page fault",
+        13 => "Load page fault",
+        15 => "Store/AMO page fault",
+        _ => "Unknown exception",
+    }
+}
+
+/// Sets up the trap frame, trap stack and trap vector of the calling hart
+/// # Safety
+/// Must be called once per hart, by the hart itself, before interrupts are enabled
+pub unsafe fn init_hart(hart_id: usize) {
+    extern "C" {
+        fn asm_trap_vector();
+    }
+    let frame = &mut (*core::ptr::addr_of_mut!(TRAP_FRAMES))[hart_id];
+    let stack = &(*core::ptr::addr_of!(TRAP_STACKS))[hart_id];
+    frame.trap_stack = stack.0.as_ptr_range().end as usize;
+    frame.hart_id = hart_id;
+    core::arch::asm!(
+        "csrw {scratch}, {frame}",
+        "csrw {tvec}, {vector}",
+        frame = in(reg) frame as *mut TrapFrame,
+        vector = in(reg) asm_trap_vector as *const () as usize,
+        scratch = const privilege::SCRATCH,
+        tvec = const privilege::TVEC,
+    );
+}
+
+/// Sets mstatus.MIE (sstatus.SIE with `sbi`) on the calling hart
+pub fn enable_interrupts() {
+    unsafe {
+        core::arch::asm!(
+            "csrs {status}, {}",
+            in(reg) privilege::STATUS_IE,
+            status = const privilege::STATUS,
+        )
+    };
+}
+
+/// Clears mstatus.MIE on the calling hart and returns whether it was set
+pub fn disable_interrupts() -> bool {
+    let mstatus: usize;
+    unsafe {
+        core::arch::asm!(
+            "csrrc {}, {status}, {}",
+            out(reg) mstatus,
+            in(reg) privilege::STATUS_IE,
+            status = const privilege::STATUS,
+        )
+    };
+    mstatus & privilege::STATUS_IE != 0
+}
+
+/// Restores mstatus.MIE to the value returned by `disable_interrupts`
+pub fn restore_interrupts(enabled: bool) {
+    if enabled {
+        enable_interrupts();
+    }
+}
+
+#[no_mangle]
+#[cfg_attr(not(feature = "user_mode"), allow(unused_variables))]
+extern "C" fn m_trap(
+    epc: usize,
+    tval: usize,
+    cause: usize,
+    hart_id: usize,
+    status: usize,
+    frame: &mut TrapFrame,
+) -> usize {
+    let code = cause & !MCAUSE_INTERRUPT;
+    // syscalls and faults of the code running in U-mode
+    #[cfg(feature = "user_mode")]
+    if cause & MCAUSE_INTERRUPT == 0 && user::from_user(status) {
+        if let Some(epc) = user::handle_exception(code, epc, tval, hart_id, frame) {
+            return epc;
+        }
+    }
+    if cause & MCAUSE_INTERRUPT == 0 {
+        panic!(
+            "{} (mcause {}) on hart {}: mepc {:#x}, mtval {:#x}",
+            exception_name(code),
+            code,
+            hart_id,
+            epc,
+            tval
+        );
+    }
+
+    match Interrupt::from_code(code) {
+        // another hart failed and wants everyone to stop
+        Some(Interrupt::Software) if shutdown::stop_requested() => crate::abort(),
+        Some(interrupt) => match handler(interrupt) {
+            Some(handler) => handler(hart_id),
+            None => unhandled_interrupt(interrupt, hart_id),
+        },
+        None => println!("Unknown interrupt {} on hart {}", code, hart_id),
+    }
+    epc
+}
+
+/// Silences an interrupt that nobody registered a handler for, so that it does not fire again
+fn unhandled_interrupt(interrupt: Interrupt, hart_id: usize) {
+    match interrupt {
+        Interrupt::Software => clint::clear_ipi(hart_id),
+        Interrupt::Timer => clint::set_mtimecmp(hart_id, u64::MAX),
+        Interrupt::External => unsafe {
+            core::arch::asm!(
+                "csrc {ie}, {}",
+                in(reg) 1usize << Interrupt::External as usize,
+                ie = const privilege::IE,
+            );
+        },
+    }
+    println!("Unhandled {:?} interrupt on hart {}", interrupt, hart_id);
+}
@@ -1,12 +1,38 @@
 // uart.rs
-// UART routines and driver
+// NS16550A UART routines and driver
 
+use crate::serial::{Serial, TxBuffer};
 use core::convert::TryInto;
 use core::fmt::Error;
 use core::fmt::Write;
 
+/// Number of polls of the line status register before giving up on the transmitter,
+/// so a missing or stuck UART cannot hang the hart that is printing
+const TX_TIMEOUT: usize = 100_000;
+/// Size of the transmit FIFO of the 16550
+const TX_FIFO_SIZE: usize = 16;
+
+// Register offsets
+const RBR_THR: usize = 0;
+const IER: usize = 1;
+const FCR_IIR: usize = 2;
+const LCR: usize = 3;
+const LSR: usize = 5;
+
+// Line status register bits
+const LSR_DR: u8 = 1 << 0;
+const LSR_THRE: u8 = 1 << 5;
+
+// Interrupt enable register bits
+const IER_RDI: u8 = 1 << 0;
+const IER_THREI: u8 = 1 << 1;
+
 pub struct Uart {
     base_address: usize,
+    clock_hz: u32,
+    baud_rate: u32,
+    /// Characters are queued here and sent from the THR empty interrupt, see `enable_buffered_tx`
+    tx_buffer: Option<TxBuffer>,
 }
 
 impl Write for Uart {
@@ -19,92 +45,189 @@ impl Write for Uart {
 }
 
 impl Uart {
-    pub fn new(base_address: usize) -> Self {
-        Uart { base_address }
+    /// `clock_hz` is the input clock of the UART, used to derive the divisor for `baud_rate`
+    pub fn new(base_address: usize, clock_hz: u32, baud_rate: u32) -> Self {
+        Uart {
+            base_address,
+            clock_hz,
+            baud_rate,
+            tx_buffer: None,
+        }
+    }
+
+    fn read(&self, offset: usize) -> u8 {
+        unsafe { (self.base_address as *const u8).add(offset).read_volatile() }
     }
 
-    pub fn init(&mut self) {
-        let ptr = self.base_address as *mut u8;
+    fn write(&mut self, offset: usize, value: u8) {
         unsafe {
-            // First, set the word length, which
-            // are bits 0 and 1 of the line control register (LCR)
-            // which is at base_address + 3
-            // We can easily write the value 3 here or 0b11, but I'm
-            // extending it so that it is clear we're setting two individual
-            // fields                             Word 0     Word 1
-            //                                    ~~~~~~     ~~~~~~
-            ptr.add(3).write_volatile((1 << 0) | (1 << 1));
-
-            // Now, enable the FIFO, which is bit index 0 of the FIFO
-            // control register (FCR at offset 2).
-            // Again, we can just write 1 here, but when we use left shift,
-            // it's easier to see that we're trying to write bit index #0.
-            ptr.add(2).write_volatile(1 << 0);
-
-            // Enable receiver buffer interrupts, which is at bit index
-            // 0 of the interrupt enable register (IER at offset 1).
-            ptr.add(1).write_volatile(1 << 0);
-
-            // If we cared about the divisor, the code below would set the divisor
-            // from a global clock rate of 22.729 MHz (22,729,000 cycles per second)
-            // to a signaling rate of 2400 (BAUD). We usually have much faster signalling
-            // rates nowadays, but this demonstrates what the divisor actually does.
-            // The formula given in the NS16500A specification for calculating the divisor
-            // is:
-            // divisor = ceil( (clock_hz) / (baud_sps x 16) )
-            // So, we substitute our values and get:
-            // divisor = ceil( 22_729_000 / (2400 x 16) )
-            // divisor = ceil( 22_729_000 / 38_400 )
-            // divisor = ceil( 591.901 ) = 592
-
-            // The divisor register is two bytes (16 bits), so we need to split the value
-            // 592 into two bytes. Typically, we would calculate this based on measuring
-            // the clock rate, but again, for our purposes [qemu], this doesn't really do
-            // anything.
-            let divisor: u16 = 592;
-            let divisor_least: u8 = (divisor & 0xff).try_into().unwrap();
-            let divisor_most: u8 = (divisor >> 8).try_into().unwrap();
-
-            // Notice that the divisor register DLL (divisor latch least) and DLM (divisor latch most)
-            // have the same base address as the receiver/transmitter and the interrupt enable register.
-            // To change what the base address points to, we open the "divisor latch" by writing 1 into
-            // the Divisor Latch Access Bit (DLAB), which is bit index 7 of the Line Control Register (LCR)
-            // which is at base_address + 3.
-            let lcr = ptr.add(3).read_volatile();
-            ptr.add(3).write_volatile(lcr | 1 << 7);
-
-            // Now, base addresses 0 and 1 point to DLL and DLM, respectively.
-            // Put the lower 8 bits of the divisor into DLL
-            ptr.add(0).write_volatile(divisor_least);
-            ptr.add(1).write_volatile(divisor_most);
-
-            // Now that we've written the divisor, we never have to touch this again. In hardware, this
-            // will divide the global clock (22.729 MHz) into one suitable for 2,400 signals per second.
-            // So, to once again get access to the RBR/THR/IER registers, we need to close the DLAB bit
-            // by clearing it to 0. Here, we just restore the original value of lcr.
-            ptr.add(3).write_volatile(lcr);
-
-            write!(self, "UART initialized\n").unwrap();
+            (self.base_address as *mut u8)
+                .add(offset)
+                .write_volatile(value)
         }
     }
 
-    pub fn put(&mut self, c: u8) {
-        let ptr = self.base_address as *mut u8;
-        unsafe {
-            ptr.add(0).write_volatile(c);
+    fn transmit_ready(&self) -> bool {
+        self.read(LSR) & LSR_THRE != 0
+    }
+
+    /// Waits (for a bounded time) until the transmit holding register is empty
+    /// Returns false on timeout
+    fn wait_transmit_ready(&self) -> bool {
+        for _ in 0..TX_TIMEOUT {
+            if self.transmit_ready() {
+                return true;
+            }
+            core::hint::spin_loop();
         }
+        false
     }
 
-    pub fn get(&mut self) -> Option<u8> {
-        let ptr = self.base_address as *mut u8;
-        unsafe {
-            if ptr.add(5).read_volatile() & 1 == 0 {
-                // The DR bit is 0, meaning no data
-                None
-            } else {
-                // The DR bit is 1, meaning data!
-                Some(ptr.add(0).read_volatile())
+    /// Moves up to `count` characters from the buffer to the transmitter
+    fn send_buffered(&mut self, count: usize) {
+        for _ in 0..count {
+            match self.tx_buffer.as_mut().and_then(TxBuffer::pop) {
+                Some(c) => self.write(RBR_THR, c),
+                None => break,
             }
         }
     }
+
+    /// The THR empty interrupt is only enabled while there are characters waiting
+    fn update_tx_interrupt(&mut self) {
+        let pending = self.tx_buffer.as_ref().is_some_and(|tx| !tx.is_empty());
+        let ier = self.read(IER);
+        let new_ier = if pending {
+            ier | IER_THREI
+        } else {
+            ier & !IER_THREI
+        };
+        if new_ier != ier {
+            self.write(IER, new_ier);
+        }
+    }
+}
+
+impl Serial for Uart {
+    fn init(&mut self) {
+        // First, set the word length, which
+        // are bits 0 and 1 of the line control register (LCR)
+        // which is at base_address + 3
+        // We can easily write the value 3 here or 0b11, but I'm
+        // extending it so that it is clear we're setting two individual
+        // fields                   Word 0     Word 1
+        //                          ~~~~~~     ~~~~~~
+        self.write(LCR, (1 << 0) | (1 << 1));
+
+        // Now, enable the FIFO, which is bit index 0 of the FIFO
+        // control register (FCR at offset 2).
+        // Again, we can just write 1 here, but when we use left shift,
+        // it's easier to see that we're trying to write bit index #0.
+        self.write(FCR_IIR, 1 << 0);
+
+        // Enable receiver buffer interrupts, which is at bit index
+        // 0 of the interrupt enable register (IER at offset 1).
+        self.write(IER, IER_RDI);
+
+        self.set_baud_rate(self.baud_rate);
+
+        write!(self, "UART initialized\r\n").unwrap();
+    }
+
+    /// Programs the divisor latch for `baud_rate` from the input clock
+    fn set_baud_rate(&mut self, baud_rate: u32) {
+        self.baud_rate = baud_rate;
+        // The formula given in the NS16500A specification for calculating the divisor
+        // is:
+        // divisor = ceil( (clock_hz) / (baud_sps x 16) )
+        // e.g. for the 3.6864 MHz clock of the QEMU virt UART at 115200 baud:
+        // divisor = ceil( 3_686_400 / (115_200 x 16) ) = 2
+        let divisor: u16 = self
+            .clock_hz
+            .div_ceil(baud_rate * 16)
+            .clamp(1, u16::MAX as u32)
+            .try_into()
+            .unwrap();
+        let divisor_least: u8 = (divisor & 0xff).try_into().unwrap();
+        let divisor_most: u8 = (divisor >> 8).try_into().unwrap();
+
+        // Notice that the divisor register DLL (divisor latch least) and DLM (divisor latch most)
+        // have the same base address as the receiver/transmitter and the interrupt enable register.
+        // To change what the base address points to, we open the "divisor latch" by writing 1 into
+        // the Divisor Latch Access Bit (DLAB), which is bit index 7 of the Line Control Register (LCR)
+        // which is at base_address + 3.
+        let lcr = self.read(LCR);
+        self.write(LCR, lcr | 1 << 7);
+
+        // Now, base addresses 0 and 1 point to DLL and DLM, respectively.
+        // Put the lower 8 bits of the divisor into DLL
+        self.write(0, divisor_least);
+        self.write(1, divisor_most);
+
+        // To once again get access to the RBR/THR/IER registers, we need to close the DLAB bit
+        // by clearing it to 0. Here, we just restore the original value of lcr.
+        self.write(LCR, lcr);
+    }
+
+    fn baud_rate(&self) -> u32 {
+        self.baud_rate
+    }
+
+    /// Switches to buffered transmission
+    /// The buffer is drained when the transmitter is found empty while printing and from the
+    /// THR empty interrupt, which must be routed to `handle_interrupt`
+    fn enable_buffered_tx(&mut self) {
+        self.tx_buffer = Some(TxBuffer::new());
+    }
+
+    fn put(&mut self, c: u8) {
+        if self.tx_buffer.is_none() {
+            // after a timeout the character is written anyway, at worst it is lost
+            self.wait_transmit_ready();
+            self.write(RBR_THR, c);
+            return;
+        }
+        if self.tx_buffer.as_ref().is_some_and(TxBuffer::is_full) {
+            // buffer full, make room by sending the oldest character synchronously
+            self.wait_transmit_ready();
+            self.send_buffered(1);
+        }
+        if let Some(tx) = self.tx_buffer.as_mut() {
+            tx.push(c);
+        }
+        if self.transmit_ready() {
+            self.send_buffered(TX_FIFO_SIZE);
+        }
+        self.update_tx_interrupt();
+    }
+
+    /// Services the transmit side of a UART interrupt, refilling the FIFO if it is empty
+    /// Received characters must be read with `get` first
+    fn handle_interrupt(&mut self) {
+        if self.transmit_ready() {
+            self.send_buffered(TX_FIFO_SIZE);
+        }
+        self.update_tx_interrupt();
+    }
+
+    /// Sends everything still waiting in the transmit buffer
+    fn flush(&mut self) {
+        while self.tx_buffer.as_ref().is_some_and(|tx| !tx.is_empty()) {
+            if !self.wait_transmit_ready() {
+                break;
+            }
+            self.send_buffered(TX_FIFO_SIZE);
+        }
+        self.update_tx_interrupt();
+    }
+
+    fn get(&mut self) -> Option<u8> {
+        if self.read(LSR) & LSR_DR == 0 {
+            // The DR bit is 0, meaning no data
+            None
+        } else {
+            // The DR bit is 1, meaning data!
+            Some(self.read(RBR_THR))
+        }
+    }
 }
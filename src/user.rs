@@ -0,0 +1,280 @@
+// user.rs
+// U-mode execution, for the `user_mode` feature
+// `run` calls a closure in U-mode on a stack of its own; the closure reaches the M-mode runtime
+// through `ecall` (a7 = syscall number, a0 and a1 = arguments, result in a0) with the
+// wrappers below: `print`, `time`, `barrier` and `null`, the latter for measuring the cost of
+// the round trip (see `ecall_benchmark`)
+// Entering and leaving U-mode is done by asm/user.s, the ecalls are served by m_trap
+
+use crate::machine::MAX_HARTS;
+use crate::print;
+use crate::trap::TrapFrame;
+use crate::{machine, pmp, shutdown};
+use core::fmt::Write;
+use core::mem::ManuallyDrop;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+#[cfg(feature = "sbi")]
+compile_error!("user_mode needs machine mode, it cannot be combined with sbi");
+
+const USER_STACK_SIZE: usize = 32 * 1024;
+
+// Syscall numbers
+/// Ends `run`, issued by asm/user.s when the closure returns
+pub const SYS_EXIT: usize = 0;
+const SYS_PRINT: usize = 1;
+const SYS_TIME: usize = 2;
+const SYS_BARRIER: usize = 3;
+const SYS_NULL: usize = 4;
+
+/// Result of a syscall that failed, e.g. an unknown number or an invalid buffer
+const SYSCALL_ERROR: usize = usize::MAX;
+
+const MSTATUS_MPP: usize = 3 << 11;
+
+// mcause exception codes
+#[cfg(feature = "pmp")]
+const STORE_ACCESS_FAULT: usize = 7;
+const ECALL_FROM_U: usize = 8;
+
+// Indices in `TrapFrame::regs`
+const SP: usize = 2;
+const A0: usize = 10;
+const A1: usize = 11;
+const A7: usize = 17;
+
+/// Size of an ecall instruction, the trap returns past it
+const ECALL_SIZE: usize = 4;
+
+/// Number of null syscalls timed by `ecall_benchmark`
+const ECALL_ITERATIONS: u32 = 10_000;
+/// Characters buffered by `print` before making a syscall
+const PRINT_BUFFER_SIZE: usize = 128;
+
+#[repr(C, align(16))]
+struct UserStack([u8; USER_STACK_SIZE]);
+
+static mut USER_STACKS: [UserStack; MAX_HARTS] =
+    [const { UserStack([0; USER_STACK_SIZE]) }; MAX_HARTS];
+
+extern "C" {
+    /// Calls `entry(arg)` in U-mode on the stack ending at `stack_top`
+    /// Returns once `entry` has returned, see asm/user.s
+    fn enter_user(entry: extern "C" fn(*const u8), arg: *const u8, stack_top: usize);
+    /// Where M-mode resumes after the exit syscall that ends `enter_user`
+    fn user_exit();
+}
+
+/// U-mode stack of `hart_id`
+pub fn stack(hart_id: usize) -> Range<usize> {
+    let start = unsafe { core::ptr::addr_of!(USER_STACKS[hart_id]) } as usize;
+    start..start + USER_STACK_SIZE
+}
+
+/// Runs `f` in U-mode on the calling hart, with access to all the memory
+pub fn run<F: FnOnce()>(f: F) {
+    unsafe {
+        pmp::allow_all();
+        enter(f);
+    }
+}
+
+/// Runs `f` in U-mode on the calling hart with the PMP as it is programmed
+/// # Safety
+/// The PMP must at least give U-mode access to the code and to the stack of the hart
+pub unsafe fn enter<F: FnOnce()>(f: F) {
+    // `f` is moved out by the trampoline, on the user stack
+    let f = ManuallyDrop::new(f);
+    enter_user(
+        trampoline::<F>,
+        &*f as *const F as *const u8,
+        stack(crate::hart_id()).end,
+    );
+}
+
+/// Entry point in U-mode, `f` points to the closure on the stack of `enter`
+extern "C" fn trampoline<F: FnOnce()>(f: *const u8) {
+    let f = unsafe { core::ptr::read(f as *const F) };
+    f();
+}
+
+/// true if the trap was taken from U-mode, `status` is mstatus at the time of the trap
+pub fn from_user(status: usize) -> bool {
+    status & MSTATUS_MPP == 0
+}
+
+/// Handles an exception taken in U-mode
+/// Returns where to resume, or None if it is not one of ours and must be reported as usual
+#[cfg_attr(not(feature = "pmp"), allow(unused_variables))]
+pub fn handle_exception(
+    code: usize,
+    epc: usize,
+    tval: usize,
+    hart_id: usize,
+    frame: &mut TrapFrame,
+) -> Option<usize> {
+    match code {
+        // back to M-mode on the stack `enter_user` was called on
+        ECALL_FROM_U if frame.regs[A7] == SYS_EXIT => {
+            frame.regs[SP] = frame.kernel_sp;
+            unsafe { core::arch::asm!("csrs mstatus, {}", in(reg) MSTATUS_MPP) };
+            Some(user_exit as unsafe extern "C" fn() as usize)
+        }
+        ECALL_FROM_U => {
+            frame.regs[A0] = syscall_handler(frame.regs[A7], frame.regs[A0], frame.regs[A1]);
+            Some(epc + ECALL_SIZE)
+        }
+        #[cfg(feature = "pmp")]
+        STORE_ACCESS_FAULT => pmp::report_store_fault(hart_id, tval, epc),
+        _ => None,
+    }
+}
+
+/// Serves a syscall, in M-mode with interrupts disabled
+fn syscall_handler(number: usize, arg0: usize, arg1: usize) -> usize {
+    match number {
+        SYS_PRINT => match user_str(arg0, arg1) {
+            Some(s) => {
+                print!("{}", s);
+                0
+            }
+            None => SYSCALL_ERROR,
+        },
+        SYS_TIME => crate::time().as_nanos() as usize,
+        SYS_BARRIER => {
+            wait_barrier();
+            0
+        }
+        SYS_NULL => 0,
+        _ => SYSCALL_ERROR,
+    }
+}
+
+/// The string at `address`, if it is valid UTF-8 and lies in the RAM
+fn user_str(address: usize, len: usize) -> Option<&'static str> {
+    let machine = machine::get();
+    let ram = machine.memory_start..machine.memory_start + machine.memory_size;
+    let end = address.checked_add(len)?;
+    if !ram.contains(&address) || end > ram.end {
+        return None;
+    }
+    let bytes = unsafe { core::slice::from_raw_parts(address as *const u8, len) };
+    core::str::from_utf8(bytes).ok()
+}
+
+static BARRIER_WAITING: AtomicUsize = AtomicUsize::new(0);
+static BARRIER_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Waits until every hart has reached the barrier, the last one to arrive releases the others
+fn wait_barrier() {
+    let generation = BARRIER_GENERATION.load(Ordering::Acquire);
+    if BARRIER_WAITING.fetch_add(1, Ordering::AcqRel) + 1 == crate::running_harts() {
+        BARRIER_WAITING.store(0, Ordering::Relaxed);
+        BARRIER_GENERATION.fetch_add(1, Ordering::Release);
+        return;
+    }
+    while BARRIER_GENERATION.load(Ordering::Acquire) == generation {
+        // interrupts are disabled here, so the stop IPI of a failing hart is not taken
+        if shutdown::stop_requested() {
+            crate::abort();
+        }
+        core::hint::spin_loop();
+    }
+}
+
+fn syscall(number: usize, arg0: usize, arg1: usize) -> usize {
+    let result;
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") arg0 => result,
+            in("a1") arg1,
+            in("a7") number,
+        )
+    };
+    result
+}
+
+/// Time since boot, in U-mode
+pub fn time() -> Duration {
+    Duration::from_nanos(syscall(SYS_TIME, 0, 0) as u64)
+}
+
+/// Waits for all the harts, in U-mode
+pub fn barrier() {
+    syscall(SYS_BARRIER, 0, 0);
+}
+
+/// Does nothing but enter and leave M-mode
+pub fn null() {
+    syscall(SYS_NULL, 0, 0);
+}
+
+/// Prints to the console, in U-mode
+/// The output is sent in chunks of up to `PRINT_BUFFER_SIZE` characters, each of them printed
+/// while holding the console lock
+pub fn print(args: core::fmt::Arguments) {
+    let mut writer = PrintBuffer {
+        data: [0; PRINT_BUFFER_SIZE],
+        len: 0,
+    };
+    let _ = writer.write_fmt(args);
+    writer.flush();
+}
+
+struct PrintBuffer {
+    data: [u8; PRINT_BUFFER_SIZE],
+    len: usize,
+}
+
+impl PrintBuffer {
+    fn flush(&mut self) {
+        if self.len > 0 {
+            send(&self.data[..self.len]);
+            self.len = 0;
+        }
+    }
+}
+
+/// Prints `bytes`, which must be valid UTF-8
+fn send(bytes: &[u8]) {
+    syscall(SYS_PRINT, bytes.as_ptr() as usize, bytes.len());
+}
+
+impl Write for PrintBuffer {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        // whole strings only, so every chunk is valid UTF-8
+        if self.len + s.len() > PRINT_BUFFER_SIZE {
+            self.flush();
+        }
+        if s.len() > PRINT_BUFFER_SIZE {
+            send(s.as_bytes());
+        } else {
+            self.data[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+        }
+        Ok(())
+    }
+}
+
+/// Measures the round trip of a syscall from U-mode on every hart
+/// Must be called by all the harts taking part in the benchmark
+pub fn ecall_benchmark(hart_id: usize) {
+    run(|| {
+        barrier();
+        let t = time();
+        for _ in 0..ECALL_ITERATIONS {
+            null();
+        }
+        let elapsed = time() - t;
+        barrier();
+        print(format_args!(
+            "Hart {}: ecall round trip {:?} ({} calls)\r\n",
+            hart_id,
+            elapsed / ECALL_ITERATIONS,
+            ECALL_ITERATIONS
+        ));
+    });
+}
@@ -0,0 +1,69 @@
+// watchdog.rs
+// Stops a benchmark that does not complete within its deadline
+// Uses the CLINT timer of the hart that starts it, so that hart must have MTIE set in mie
+// (hart 0 does, see boot.s)
+
+use crate::machine::{self, MAX_HARTS};
+use crate::trap::{self, Interrupt};
+use crate::{clint, shutdown};
+use crate::{print, println};
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+/// Anything that can describe its progress when the watchdog fires
+pub trait Watchable: Sync {
+    fn report(&self);
+}
+
+static DONE: [AtomicBool; MAX_HARTS] = [const { AtomicBool::new(false) }; MAX_HARTS];
+static mut WATCHED: Option<&'static dyn Watchable> = None;
+static mut TIMEOUT: Duration = Duration::ZERO;
+
+fn to_ticks(duration: Duration) -> u64 {
+    let frequency = machine::get().timebase_frequency;
+    duration.as_secs() * frequency + duration.subsec_nanos() as u64 * frequency / 1_000_000_000
+}
+
+/// Arms the watchdog on the calling hart
+/// If `stop` is not called within `timeout`, the state of `watched` and the harts that did not
+/// call `hart_done` are reported and the run is terminated
+pub fn start(timeout: Duration, watched: &'static dyn Watchable) {
+    unsafe {
+        *core::ptr::addr_of_mut!(WATCHED) = Some(watched);
+        *core::ptr::addr_of_mut!(TIMEOUT) = timeout;
+    }
+    trap::register_handler(Interrupt::Timer, on_timeout);
+    clint::set_mtimecmp(crate::hart_id(), clint::mtime() + to_ticks(timeout));
+}
+
+/// Marks the work of `hart_id` as completed
+pub fn hart_done(hart_id: usize) {
+    DONE[hart_id].store(true, Ordering::SeqCst);
+}
+
+/// Disarms the watchdog, to be called by the hart that started it once every hart is done
+pub fn stop() {
+    clint::set_mtimecmp(crate::hart_id(), u64::MAX);
+    DONE.iter()
+        .for_each(|done| done.store(false, Ordering::SeqCst));
+}
+
+fn on_timeout(hart_id: usize) {
+    clint::set_mtimecmp(hart_id, u64::MAX);
+    if !shutdown::request_stop() {
+        crate::abort();
+    }
+    let (watched, timeout) =
+        unsafe { (*core::ptr::addr_of!(WATCHED), *core::ptr::addr_of!(TIMEOUT)) };
+    println!("Watchdog: benchmark did not complete within {:?}", timeout);
+    if let Some(watched) = watched {
+        watched.report();
+    }
+    print!("Missing harts:");
+    (0..crate::n_harts())
+        .filter(|&hart| !DONE[hart].load(Ordering::SeqCst))
+        .for_each(|hart| print!(" {}", hart));
+    println!();
+    shutdown::stop_other_harts(hart_id);
+    shutdown::exit_failure(shutdown::EXIT_WATCHDOG);
+}